@@ -1,83 +1,166 @@
-use std::{process::exit, sync::mpsc};
-
-use eframe::egui::{self, Context, Slider, Ui, Visuals};
-
-use crate::{canvas::Canvas, scene::Scene};
-
-struct Visible {
-    polygon: bool,
-    mesh: bool,
-    filling: bool,
-    light_pos: bool,
-    normals: bool,
+use std::sync::mpsc;
+
+use eframe::egui::{
+    self, Button, Color32, Context, Key, Modifiers, PointerButton, Response, Slider, Stroke, Ui,
+    Visuals,
+};
+
+use bezier_surface::{
+    Antialiasing, Canvas, Color, Coloring, Edit, History, Light, LightKind, Point3, PointHandle,
+    Quaternion, Rasterizer, RenderOptions, Scene, ShadingModel, StlFormat, StlOptions,
+    TextureFilter, ToneMapping, Vector3, Visible,
+};
+
+/// Parameters changed continuously with sliders and the mouse, recorded
+/// in the history once the pointer is released
+#[derive(Clone)]
+struct Params {
+    material: (f32, f32, f32, i32, ShadingModel),
+    /// `None` while the surface is textured
+    color: Option<[f32; 3]>,
+    /// `None` without a texture or normal map respectively
+    texture_filter: Option<TextureFilter>,
+    normal_map_filter: Option<TextureFilter>,
+    lights: Vec<Light>,
+    ambient: Color,
+    rotation: Quaternion,
+    resolution: usize,
 }
 
-impl Default for Visible {
-    fn default() -> Self {
+impl Params {
+    fn of(scene: &Scene) -> Self {
+        let mat = &scene.material;
         Self {
-            polygon: false,
-            mesh: false,
-            filling: true,
-            light_pos: true,
-            normals: false,
+            material: (mat.ka, mat.kd, mat.ks, mat.m, mat.shading),
+            color: match mat.coloring {
+                Coloring::Solid(c) => Some(c.as_slice()),
+                Coloring::Texture(_) => None,
+            },
+            texture_filter: match &mat.coloring {
+                Coloring::Solid(_) => None,
+                Coloring::Texture(t) => Some(t.filter),
+            },
+            normal_map_filter: mat.normal_map.as_ref().map(|t| t.filter),
+            lights: scene.lights.clone(),
+            ambient: scene.ambient,
+            rotation: scene.orientation(),
+            resolution: scene.mesh_resolution(),
         }
     }
 }
 
+/// Whether the lights were added, removed or edited, ignoring the positions
+/// following their animation
+fn lights_edited(now: &[Light], before: &[Light]) -> bool {
+    now.len() != before.len()
+        || now.iter().zip(before).any(|(a, b)| {
+            let animated = a.is_animating || b.is_animating;
+            a.kind != b.kind
+                || a.dir != b.dir
+                || a.color != b.color
+                || a.intensity != b.intensity
+                || a.attenuation != b.attenuation
+                || a.pos.z != b.pos.z
+                || (!animated && (a.pos.x != b.pos.x || a.pos.y != b.pos.y))
+        })
+}
+
+/// Radio buttons choosing how a texture is filtered
+fn texture_filter(ui: &mut Ui, filter: &mut TextureFilter) {
+    ui.horizontal_wrapped(|ui| {
+        ui.radio_value(filter, TextureFilter::Nearest, "najbliższy");
+        ui.radio_value(filter, TextureFilter::Bilinear, "dwuliniowy");
+        ui.radio_value(filter, TextureFilter::Trilinear, "trójliniowy");
+        ui.radio_value(filter, TextureFilter::Anisotropic, "anizotropowy");
+    });
+}
+
+/// Control points being dragged in the viewport
+struct DraggedPoints {
+    handles: Vec<PointHandle>,
+    /// total displacement since the drag started
+    total: Vector3,
+}
+
 pub struct PolygonApp {
     canvas: Canvas,
     scene: Scene,
     visible: Visible,
     rx_tex: Option<mpsc::Receiver<String>>,
     rx_nor: Option<mpsc::Receiver<String>>,
+    rx_obj: Option<mpsc::Receiver<String>>,
+    rx_stl: Option<mpsc::Receiver<String>>,
+    rx_gltf: Option<mpsc::Receiver<String>>,
+    rx_open: Option<mpsc::Receiver<String>>,
+    rx_save: Option<mpsc::Receiver<String>>,
+    stl_options: StlOptions,
+    render_options: RenderOptions,
+    /// canvas pixels per physical pixel of the viewport
+    render_scale: f32,
+    /// light edited in the side panel
+    selected_light: usize,
+    dragged: Option<DraggedPoints>,
+    history: History,
+    /// parameters as of the last recorded edit
+    committed: Params,
 }
 
 impl PolygonApp {
-    pub fn new() -> Self {
-        let fname = std::env::args().skip(1).next();
-        let scene = Scene::from_file(fname);
-        match scene {
-            Err(e) => {
-                eprintln!("{e}");
-                exit(1)
-            }
-            Ok(scene) => Self {
-                canvas: Canvas::new(800, 800),
-                scene,
-                visible: Visible::default(),
-                rx_tex: None,
-                rx_nor: None,
+    pub fn new(scene: Scene, draw_normals: bool) -> Self {
+        Self {
+            canvas: Canvas::new(800, 800),
+            committed: Params::of(&scene),
+            scene,
+            visible: Visible {
+                normals: draw_normals,
+                ..Default::default()
             },
+            rx_tex: None,
+            rx_nor: None,
+            rx_obj: None,
+            rx_stl: None,
+            rx_gltf: None,
+            rx_open: None,
+            rx_save: None,
+            stl_options: StlOptions::default(),
+            render_options: RenderOptions::default(),
+            render_scale: 1.0,
+            selected_light: 0,
+            dragged: None,
+            history: History::default(),
         }
     }
 }
 
 impl PolygonApp {
     fn rotations(&mut self, ui: &mut Ui) {
-        let mut ox = self.scene.rot_ox().to_degrees();
-        ui.label("OX");
-        ui.add(
-            Slider::new(&mut ox, -90.0..=90.0)
-                .suffix("°")
-                .max_decimals(0),
-        );
-        self.scene.rotate_ox(ox.to_radians() - self.scene.rot_ox());
-
-        let mut oz = self.scene.rot_oz().to_degrees();
-        ui.label("OZ");
-        ui.add(
-            Slider::new(&mut oz, -90.0..=90.0)
-                .suffix("°")
-                .fixed_decimals(0),
-        );
-        self.scene.rotate_oz(oz.to_radians() - self.scene.rot_oz());
+        let (ox, oy, oz) = self.scene.rotation();
+        let mut angles = [ox, oy, oz].map(f32::to_degrees);
+        let mut changed = false;
+        for ((label, range), angle) in [
+            ("OX", -180.0..=180.0),
+            ("OY", -90.0..=90.0),
+            ("OZ", -180.0..=180.0),
+        ]
+        .into_iter()
+        .zip(&mut angles)
+        {
+            ui.label(label);
+            let slider = Slider::new(angle, range).suffix("°").fixed_decimals(0);
+            changed |= ui.add(slider).changed();
+        }
+        if changed {
+            let [ox, oy, oz] = angles.map(f32::to_radians);
+            self.scene.set_rotation(ox, oy, oz);
+        }
     }
 
     fn mesh_resolution(&mut self, ui: &mut Ui) {
         let mut n = self.scene.mesh_resolution();
         ui.label("Dokładność");
-        ui.add(Slider::new(&mut n, 2..=50));
-        self.scene.set_mesh_resolution(n);
+        if ui.add(Slider::new(&mut n, 2..=50)).changed() {
+            self.scene.set_mesh_resolution(n);
+        }
     }
 
     fn visibility(&mut self, ui: &mut Ui) {
@@ -88,26 +171,119 @@ impl PolygonApp {
         ui.checkbox(&mut self.visible.normals, "wektory normalne");
     }
 
+    fn render_settings(&mut self, ui: &mut Ui) {
+        ui.checkbox(
+            &mut self.render_options.perspective_correct,
+            "interpolacja z korekcją perspektywy",
+        );
+        let max = 2 * std::thread::available_parallelism().map_or(1, |n| n.get());
+        ui.label("Wątki renderowania");
+        ui.add(Slider::new(&mut self.render_options.threads, 1..=max));
+        ui.label("Rasteryzacja");
+        let rasterizer = &mut self.render_options.rasterizer;
+        ui.horizontal(|ui| {
+            ui.radio_value(rasterizer, Rasterizer::Scanline, "scanline");
+            ui.radio_value(rasterizer, Rasterizer::EdgeFunction, "funkcje krawędzi");
+        });
+        ui.label("Antyaliasing");
+        let aa = &mut self.render_options.antialiasing;
+        ui.horizontal_wrapped(|ui| {
+            ui.radio_value(aa, Antialiasing::Off, "brak");
+            ui.radio_value(aa, Antialiasing::Supersampling(2), "SSAA 2×2");
+            ui.radio_value(aa, Antialiasing::Supersampling(3), "SSAA 3×3");
+            ui.radio_value(aa, Antialiasing::Multisampling(4), "MSAA 4×");
+            ui.radio_value(aa, Antialiasing::Multisampling(8), "MSAA 8×");
+        });
+        ui.label("Skala renderowania");
+        ui.add(Slider::new(&mut self.render_scale, 0.25..=1.0).fixed_decimals(2));
+        ui.label("Mapowanie tonów");
+        let tone_mapping = &mut self.render_options.tone_mapping;
+        ui.horizontal(|ui| {
+            ui.radio_value(tone_mapping, ToneMapping::Exposure, "ekspozycja");
+            ui.radio_value(tone_mapping, ToneMapping::Reinhard, "Reinhard");
+            ui.radio_value(tone_mapping, ToneMapping::AcesFilmic, "ACES");
+        });
+        ui.label("Ekspozycja");
+        ui.add(
+            Slider::new(&mut self.render_options.exposure, -4.0..=4.0)
+                .suffix(" EV")
+                .fixed_decimals(1),
+        );
+    }
+
+    fn shadow_settings(&mut self, ui: &mut Ui) {
+        let shadows = &mut self.render_options.shadows;
+        ui.checkbox(&mut shadows.enabled, "cienie");
+        ui.add_enabled_ui(shadows.enabled, |ui| {
+            ui.label("Rozdzielczość mapy cieni");
+            ui.horizontal(|ui| {
+                for res in [256, 512, 1024, 2048] {
+                    ui.radio_value(&mut shadows.resolution, res, res.to_string());
+                }
+            });
+            ui.label("Przesunięcie głębokości cieni");
+            ui.add(Slider::new(&mut shadows.bias, 0.0..=20.0).fixed_decimals(1));
+        });
+    }
+
     fn surface_props(&mut self, ui: &mut Ui) {
-        ui.label("Matowość (kd)");
-        ui.add(Slider::new(&mut self.scene.material.kd, 0.0..=1.0).fixed_decimals(2));
-        ui.label("Połysk (ks)");
-        ui.add(Slider::new(&mut self.scene.material.ks, 0.0..=1.0).fixed_decimals(2));
-        ui.label("Wykładnik zwierciadlany (m)");
-        ui.add(Slider::new(&mut self.scene.material.m, 1..=100));
+        let material = &mut self.scene.material;
+        ui.label("Model oświetlenia");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut material.shading, ShadingModel::Phong, "Phong");
+            ui.radio_value(
+                &mut material.shading,
+                ShadingModel::BlinnPhong,
+                "Blinn-Phong",
+            );
+            let is_pbr = matches!(material.shading, ShadingModel::CookTorrance { .. });
+            if ui.radio(is_pbr, "Cook-Torrance").clicked() && !is_pbr {
+                material.shading = ShadingModel::cook_torrance();
+            }
+        });
+
+        ui.label("Światło otoczenia (ka)");
+        ui.add(Slider::new(&mut material.ka, 0.0..=1.0).fixed_decimals(2));
+        if let ShadingModel::CookTorrance {
+            metallic,
+            roughness,
+        } = &mut material.shading
+        {
+            ui.label("Metaliczność");
+            ui.add(Slider::new(metallic, 0.0..=1.0).fixed_decimals(2));
+            ui.label("Chropowatość");
+            ui.add(Slider::new(roughness, 0.0..=1.0).fixed_decimals(2));
+        } else {
+            ui.label("Matowość (kd)");
+            ui.add(Slider::new(&mut material.kd, 0.0..=1.0).fixed_decimals(2));
+            ui.label("Połysk (ks)");
+            ui.add(Slider::new(&mut material.ks, 0.0..=1.0).fixed_decimals(2));
+            ui.label("Wykładnik zwierciadlany (m)");
+            ui.add(Slider::new(&mut material.m, 1..=100));
+        }
     }
 
     fn pick_image(&mut self, tx: mpsc::Sender<String>) {
+        // https://docs.rs/image/latest/image/codecs/index.html#supported-formats
+        self.pick_open_path(
+            tx,
+            "image",
+            &[
+                "png", "jpg", "gif", "bmp", "hdr", "ico", "jpeg", "pnm", "tiff", "webp",
+            ],
+        );
+    }
+
+    fn pick_open_path(
+        &mut self,
+        tx: mpsc::Sender<String>,
+        name: &'static str,
+        extensions: &'static [&'static str],
+    ) {
         std::thread::spawn(move || {
-            // https://docs.rs/image/latest/image/codecs/index.html#supported-formats
             let current_dir = std::env::current_dir().unwrap_or_default();
             if let Some(path) = rfd::FileDialog::new()
-                .add_filter(
-                    "image",
-                    &[
-                        "png", "jpg", "gif", "bmp", "hdr", "ico", "jpeg", "pnm", "tiff", "webp",
-                    ],
-                )
+                .add_filter(name, extensions)
                 .set_directory(current_dir)
                 .pick_file()
             {
@@ -117,12 +293,139 @@ impl PolygonApp {
         });
     }
 
+    fn pick_save_path(
+        &mut self,
+        tx: mpsc::Sender<String>,
+        name: &'static str,
+        extensions: &'static [&'static str],
+    ) {
+        std::thread::spawn(move || {
+            let current_dir = std::env::current_dir().unwrap_or_default();
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter(name, extensions)
+                .set_directory(current_dir)
+                .set_file_name(format!("surface.{}", extensions[0]))
+                .save_file()
+            {
+                let s = path.display().to_string();
+                let _ = tx.send(s);
+            }
+        });
+    }
+
+    fn scene_menu(&mut self, ui: &mut Ui) {
+        if ui.button("Otwórz scenę...").clicked() {
+            let (tx, rx) = mpsc::channel();
+            self.rx_open = Some(rx);
+            self.pick_open_path(tx, "scena", &["json"]);
+            ui.close();
+        }
+
+        if ui.button("Zapisz scenę...").clicked() {
+            let (tx, rx) = mpsc::channel();
+            self.rx_save = Some(rx);
+            self.pick_save_path(tx, "scena", &["json"]);
+            ui.close();
+        }
+    }
+
+    fn poll_scene_files(&mut self) {
+        if let Some(rx) = &self.rx_open
+            && let Ok(path) = rx.try_recv()
+        {
+            match Scene::open(path.as_ref()) {
+                Ok((scene, visible)) => {
+                    self.committed = Params::of(&scene);
+                    self.scene = scene;
+                    self.visible = visible;
+                    self.history = History::default();
+                    self.dragged = None;
+                    self.selected_light = 0;
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+            self.rx_open = None;
+        }
+
+        if let Some(rx) = &self.rx_save
+            && let Ok(path) = rx.try_recv()
+        {
+            if let Err(e) = self.scene.save(path.as_ref(), &self.visible) {
+                eprintln!("{e}");
+            }
+            self.rx_save = None;
+        }
+    }
+
+    fn export_menu(&mut self, ui: &mut Ui) {
+        if ui.button("Eksportuj OBJ...").clicked() {
+            let (tx, rx) = mpsc::channel();
+            self.rx_obj = Some(rx);
+            self.pick_save_path(tx, "Wavefront OBJ", &["obj"]);
+            ui.close();
+        }
+
+        if ui.button("Eksportuj glTF...").clicked() {
+            let (tx, rx) = mpsc::channel();
+            self.rx_gltf = Some(rx);
+            self.pick_save_path(tx, "glTF", &["glb", "gltf"]);
+            ui.close();
+        }
+
+        ui.menu_button("Eksportuj STL", |ui| {
+            let opts = &mut self.stl_options;
+            ui.radio_value(&mut opts.format, StlFormat::Binary, "binarny");
+            ui.radio_value(&mut opts.format, StlFormat::Ascii, "tekstowy");
+            ui.label("Grubość bryły (0 = otwarta powierzchnia)");
+            ui.add(Slider::new(&mut opts.thickness, 0.0..=50.0).fixed_decimals(1));
+            if ui.button("Zapisz...").clicked() {
+                let (tx, rx) = mpsc::channel();
+                self.rx_stl = Some(rx);
+                self.pick_save_path(tx, "STL", &["stl"]);
+                ui.close();
+            }
+        });
+    }
+
+    fn poll_exports(&mut self) {
+        if let Some(rx) = &self.rx_obj
+            && let Ok(path) = rx.try_recv()
+        {
+            if let Err(e) = self.scene.export_obj(path.into()) {
+                eprintln!("{e}");
+            }
+            self.rx_obj = None;
+        }
+
+        if let Some(rx) = &self.rx_stl
+            && let Ok(path) = rx.try_recv()
+        {
+            if let Err(e) = self.scene.export_stl(path.into(), &self.stl_options) {
+                eprintln!("{e}");
+            }
+            self.rx_stl = None;
+        }
+
+        if let Some(rx) = &self.rx_gltf
+            && let Ok(path) = rx.try_recv()
+        {
+            if let Err(e) = self.scene.export_gltf(path.into()) {
+                eprintln!("{e}");
+            }
+            self.rx_gltf = None;
+        }
+    }
+
     fn pick_surface(&mut self, ui: &mut Ui) {
         ui.horizontal(|ui| {
             let mut col = self.scene.material_color();
             ui.color_edit_button_rgb(&mut col);
             if col != self.scene.material_color() {
-                self.scene.set_material_color(col);
+                let old = self.scene.set_material_color(col);
+                if let Coloring::Texture(_) = old {
+                    self.history.push(Edit::Coloring(old));
+                    self.committed.color = Some(col);
+                }
             }
 
             if ui.button("Tekstura z pliku...").clicked() {
@@ -135,39 +438,166 @@ impl PolygonApp {
         if let Some(rx) = &self.rx_tex
             && let Ok(path) = rx.try_recv()
         {
-            self.scene.set_texture(path.into());
+            match self.scene.set_texture(path.into()) {
+                Ok(old) => {
+                    self.history.push(Edit::Coloring(old));
+                    self.committed = Params::of(&self.scene);
+                }
+                Err(e) => eprintln!("{e}"),
+            }
             self.rx_tex = None;
         }
+
+        if let Coloring::Texture(t) = &mut self.scene.material.coloring {
+            ui.label("Filtrowanie tekstury");
+            texture_filter(ui, &mut t.filter);
+        }
+    }
+
+    fn lights_list(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let mut col = self.scene.ambient_color();
+            ui.color_edit_button_rgb(&mut col);
+            self.scene.set_ambient_color(col);
+            ui.label("Światło otoczenia");
+        });
+        ui.label("Światła");
+        ui.horizontal_wrapped(|ui| {
+            for i in 0..self.scene.lights.len() {
+                ui.selectable_value(&mut self.selected_light, i, (i + 1).to_string());
+            }
+            if ui.small_button("+").clicked() {
+                let mut light = Light::new(Point3::new(0.0, 0.0, 300.0), Color::new(1.0, 1.0, 1.0));
+                light.is_animating = false;
+                light.place_on_orbit(0.5);
+                self.scene.lights.push(light);
+                self.selected_light = self.scene.lights.len() - 1;
+            }
+            let can_remove = self.selected_light < self.scene.lights.len();
+            if ui
+                .add_enabled(can_remove, Button::new("−").small())
+                .clicked()
+            {
+                self.scene.lights.remove(self.selected_light);
+                self.clamp_selected_light();
+            }
+        });
+    }
+
+    fn clamp_selected_light(&mut self) {
+        self.selected_light = self
+            .selected_light
+            .min(self.scene.lights.len().saturating_sub(1));
     }
 
     fn light_props(&mut self, ui: &mut Ui) {
-        ui.label("Pozycja światła (z)");
-        ui.add(Slider::new(&mut self.scene.light.pos.z, -600.0..=600.0).fixed_decimals(0));
+        let i = self.selected_light;
+        if i >= self.scene.lights.len() {
+            return;
+        }
         ui.label("Kolor światła");
-        let mut col = self.scene.light_color();
+        let mut col = self.scene.light_color(i);
         ui.color_edit_button_rgb(&mut col);
-        self.scene.set_light_color(col);
+        self.scene.set_light_color(i, col);
+
+        let light = &mut self.scene.lights[i];
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut light.kind, LightKind::Point, "punktowe");
+            ui.radio_value(&mut light.kind, LightKind::Directional, "kierunkowe");
+            let is_spot = matches!(light.kind, LightKind::Spot { .. });
+            if ui.radio(is_spot, "reflektor").clicked() && !is_spot {
+                light.kind = LightKind::spot();
+            }
+        });
+        ui.label("Natężenie");
+        ui.add(Slider::new(&mut light.intensity, 0.0..=5.0).fixed_decimals(2));
+        if light.kind != LightKind::Directional {
+            ui.label("Pozycja światła (x, y, z)");
+            // x and y follow the orbit while the light is animated
+            for coord in [&mut light.pos.x, &mut light.pos.y] {
+                let slider = Slider::new(coord, -1000.0..=1000.0).fixed_decimals(0);
+                ui.add_enabled(!light.is_animating, slider);
+            }
+            ui.add(Slider::new(&mut light.pos.z, -600.0..=600.0).fixed_decimals(0));
+
+            let attenuation = &mut light.attenuation;
+            ui.label("Tłumienie (stałe, liniowe, kwadratowe)");
+            let min_constant = attenuation.min_constant();
+            ui.add(Slider::new(&mut attenuation.constant, min_constant..=2.0).fixed_decimals(2));
+            ui.add(
+                Slider::new(&mut attenuation.linear, 0.0..=0.01)
+                    .logarithmic(true)
+                    .fixed_decimals(4),
+            );
+            ui.add(
+                Slider::new(&mut attenuation.quadratic, 0.0..=1e-4)
+                    .logarithmic(true)
+                    .fixed_decimals(6),
+            );
+            // the linear and quadratic terms may have just been zeroed
+            attenuation.constant = attenuation.constant.max(attenuation.min_constant());
+        }
+        if light.kind != LightKind::Point {
+            let d = light.dir.normalized();
+            let mut angles = [d.z.atan2(d.x), d.y.clamp(-1.0, 1.0).asin()].map(f32::to_degrees);
+            let mut changed = false;
+            ui.label("Kierunek (azymut, elewacja)");
+            changed |= ui
+                .add(
+                    Slider::new(&mut angles[0], -180.0..=180.0)
+                        .suffix("°")
+                        .fixed_decimals(0),
+                )
+                .changed();
+            changed |= ui
+                .add(
+                    Slider::new(&mut angles[1], -90.0..=90.0)
+                        .suffix("°")
+                        .fixed_decimals(0),
+                )
+                .changed();
+            if changed {
+                let [az, el] = angles.map(f32::to_radians);
+                light.dir = Vector3::new(el.cos() * az.cos(), el.sin(), el.cos() * az.sin());
+            }
+        }
+        if let LightKind::Spot { inner, outer } = &mut light.kind {
+            let mut angles = [*inner, *outer].map(f32::to_degrees);
+            ui.label("Stożek (kąt wewnętrzny, zewnętrzny)");
+            for angle in &mut angles {
+                ui.add(Slider::new(angle, 0.0..=90.0).suffix("°").fixed_decimals(0));
+            }
+            let [i, o] = angles.map(f32::to_radians);
+            (*inner, *outer) = (i.min(o), o);
+        }
     }
 
     fn light_animation(&mut self, ctx: &Context, ui: &mut Ui) {
         let dt = ctx.input(|i| i.stable_dt);
-        self.scene.light.advance_animation(dt);
+        self.scene.advance_light_animations(dt);
+        let Some(light) = self.scene.lights.get_mut(self.selected_light) else {
+            return;
+        };
+        if light.kind == LightKind::Directional {
+            return;
+        }
         ui.label("Animacja światła");
         ui.horizontal(|ui| {
-            if self.scene.light.is_animating {
+            if light.is_animating {
                 if ui.small_button("⏸").clicked() {
-                    self.scene.light.is_animating = false;
+                    light.is_animating = false;
                 }
             } else {
                 if ui.small_button("▶").clicked() {
-                    self.scene.light.is_animating = true;
+                    light.is_animating = true;
                 }
             }
-            let mut new_t = self.scene.light.t;
-            ui.add(Slider::new(&mut new_t, 0.0..=1.0).fixed_decimals(2));
-            if !self.scene.light.is_animating {
-                self.scene.light.t = new_t;
-                self.scene.light.advance_animation(dt);
+            let mut new_t = light.t;
+            let changed = ui
+                .add(Slider::new(&mut new_t, 0.0..=1.0).fixed_decimals(2))
+                .changed();
+            if changed && !light.is_animating {
+                light.place_on_orbit(new_t);
             }
         });
     }
@@ -180,19 +610,30 @@ impl PolygonApp {
                 self.pick_image(tx);
             }
 
-            if self.scene.material.normal_map.is_some() {
-                if ui.button("🗑").clicked() {
-                    self.scene.material.normal_map = None;
-                }
+            if self.scene.material.normal_map.is_some() && ui.button("🗑").clicked() {
+                let old = self.scene.material.normal_map.take();
+                self.history.push(Edit::NormalMap(old));
+                self.committed = Params::of(&self.scene);
             }
         });
 
         if let Some(rx) = &self.rx_nor
             && let Ok(path) = rx.try_recv()
         {
-            self.scene.set_normal_map(path.into());
+            match self.scene.set_normal_map(path.into()) {
+                Ok(old) => {
+                    self.history.push(Edit::NormalMap(old));
+                    self.committed = Params::of(&self.scene);
+                }
+                Err(e) => eprintln!("{e}"),
+            }
             self.rx_nor = None;
         }
+
+        if let Some(t) = &mut self.scene.material.normal_map {
+            ui.label("Filtrowanie mapy wektorów normalnych");
+            texture_filter(ui, &mut t.filter);
+        }
     }
 
     fn surface_animation(&mut self, ctx: &Context, ui: &mut Ui) {
@@ -206,16 +647,163 @@ impl PolygonApp {
                     self.scene.is_animating_surface = false;
                 }
             } else {
-                if ui.button("▶").clicked() {
+                let play = ui
+                    .add_enabled(self.scene.can_animate_surface(), Button::new("▶"))
+                    .on_disabled_hover_text("Animacja dostępna tylko dla pojedynczego płata");
+                if play.clicked() {
                     self.scene.is_animating_surface = true;
                 }
             }
         });
     }
 
-    fn light_kind(&mut self, ui: &mut Ui) {
-        ui.label("Typ światła (r = 0 dla punktowego)");
-        ui.add(Slider::new(&mut self.scene.light.r, 0..=30));
+    /// Drags control points if one was grabbed (in the view plane, or in depth
+    /// while holding Shift), otherwise orbits the camera; dragging with the
+    /// secondary or middle button pans and scrolling zooms
+    fn handle_drag(&mut self, ctx: &Context, response: &Response) {
+        if response.drag_started_by(PointerButton::Primary) && self.visible.polygon {
+            let origin = ctx.input(|i| i.pointer.press_origin());
+            self.dragged = origin
+                .and_then(|pos| self.scene.pick_point(pos, &self.canvas, 10.0))
+                .map(|handles| DraggedPoints {
+                    handles,
+                    total: Vector3::zeros(),
+                });
+        }
+
+        let delta = response.drag_delta();
+        // drag deltas are in points, as is the size of the viewport
+        let height = self.canvas.rect().height();
+        if let Some(dragged) = &mut self.dragged {
+            if delta != egui::Vec2::ZERO {
+                let camera = &self.scene.camera;
+                let (right, up, forward) = camera.basis();
+                let at = self.scene.world_point(dragged.handles[0]);
+                let pixel = camera.pixel_size(camera.depth(at), height);
+                let delta = if ctx.input(|i| i.modifiers.shift) {
+                    forward * (delta.y * pixel)
+                } else {
+                    (right * delta.x - up * delta.y) * pixel
+                };
+                let delta = self.scene.orientation().conjugate().rotate(delta);
+                self.scene.move_points(&dragged.handles, delta);
+                dragged.total = dragged.total + delta;
+            }
+
+            if response.drag_stopped() {
+                let dragged = self.dragged.take().unwrap();
+                self.history.push(Edit::MovePoints {
+                    handles: dragged.handles,
+                    delta: dragged.total * -1.0,
+                });
+            }
+            return;
+        }
+
+        let camera = &mut self.scene.camera;
+        if response.dragged_by(PointerButton::Primary) {
+            let strength = 8e-3;
+            camera.orbit(delta.x * strength, delta.y * strength);
+        } else if response.dragged_by(PointerButton::Secondary)
+            || response.dragged_by(PointerButton::Middle)
+        {
+            camera.pan(delta.x, delta.y, height);
+        }
+
+        if response.hovered() {
+            let scroll = ctx.input(|i| i.smooth_scroll_delta.y);
+            if scroll != 0.0 {
+                camera.zoom((-scroll * 2e-3).exp());
+            }
+        }
+    }
+
+    fn edit_menu(&mut self, ui: &mut Ui) {
+        let undo = Button::new("Cofnij").shortcut_text("Ctrl+Z");
+        if ui.add_enabled(self.history.can_undo(), undo).clicked() {
+            self.undo();
+            ui.close();
+        }
+        let redo = Button::new("Ponów").shortcut_text("Ctrl+Shift+Z");
+        if ui.add_enabled(self.history.can_redo(), redo).clicked() {
+            self.redo();
+            ui.close();
+        }
+    }
+
+    fn undo(&mut self) {
+        self.history.undo(&mut self.scene);
+        self.committed = Params::of(&self.scene);
+        self.clamp_selected_light();
+    }
+
+    fn redo(&mut self) {
+        self.history.redo(&mut self.scene);
+        self.committed = Params::of(&self.scene);
+        self.clamp_selected_light();
+    }
+
+    fn undo_shortcuts(&mut self, ctx: &Context) {
+        let (redo, undo) = ctx.input_mut(|i| {
+            let redo = i.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+            (redo, i.consume_key(Modifiers::COMMAND, Key::Z))
+        });
+        if redo {
+            self.redo();
+        } else if undo {
+            self.undo();
+        }
+    }
+
+    /// Records the slider and rotation changes made since the last edit,
+    /// once the pointer is released so that a whole drag becomes one edit
+    fn commit_params(&mut self, ctx: &Context) {
+        if ctx.input(|i| i.pointer.any_down()) {
+            return;
+        }
+
+        let now = Params::of(&self.scene);
+        let before = &self.committed;
+        if now.material != before.material {
+            let (ka, kd, ks, m, shading) = before.material;
+            self.history.push(Edit::Material {
+                ka,
+                kd,
+                ks,
+                m,
+                shading,
+            });
+        }
+        if now.color != before.color
+            && let Some(c) = before.color
+        {
+            let solid = Coloring::Solid(Color::from_slice(c));
+            self.history.push(Edit::Coloring(solid));
+        }
+        // a filter is only edited on the same texture, replacing it is recorded on its own
+        if let (Some(a), Some(b)) = (now.texture_filter, before.texture_filter)
+            && a != b
+        {
+            self.history.push(Edit::TextureFilter(b));
+        }
+        if let (Some(a), Some(b)) = (now.normal_map_filter, before.normal_map_filter)
+            && a != b
+        {
+            self.history.push(Edit::NormalMapFilter(b));
+        }
+        if lights_edited(&now.lights, &before.lights) {
+            self.history.push(Edit::Lights(before.lights.clone()));
+        }
+        if now.ambient != before.ambient {
+            self.history.push(Edit::Ambient(before.ambient));
+        }
+        if now.rotation != before.rotation {
+            self.history.push(Edit::Rotation(before.rotation));
+        }
+        if now.resolution != before.resolution {
+            self.history.push(Edit::Resolution(before.resolution));
+        }
+        self.committed = now;
     }
 }
 
@@ -226,16 +814,26 @@ impl eframe::App for PolygonApp {
             style.visuals = Visuals::dark();
         });
 
+        self.undo_shortcuts(ctx);
+
         egui::TopBottomPanel::top("menu").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.menu_button("Plik", |ui| {
+                    self.scene_menu(ui);
+                    ui.separator();
+                    self.export_menu(ui);
+                    ui.separator();
                     if ui.button("Wyjdź").clicked() {
                         std::process::exit(0);
                     }
                 });
+                ui.menu_button("Edycja", |ui| self.edit_menu(ui));
             });
         });
 
+        self.poll_scene_files();
+        self.poll_exports();
+
         egui::SidePanel::right("right_panel")
             .resizable(false)
             .show(ctx, |ui| {
@@ -246,6 +844,9 @@ impl eframe::App for PolygonApp {
                 self.visibility(ui);
                 ui.separator();
 
+                self.render_settings(ui);
+                ui.separator();
+
                 self.surface_props(ui);
 
                 ui.label("Kolor powierzchni");
@@ -253,44 +854,48 @@ impl eframe::App for PolygonApp {
                 self.normal_map(ui);
 
                 ui.separator();
+                self.lights_list(ui);
                 self.light_props(ui);
                 self.light_animation(ctx, ui);
-                self.light_kind(ui);
+                self.shadow_settings(ui);
 
                 ui.separator();
                 self.surface_animation(ctx, ui);
             });
 
-        self.canvas.clear(None);
+        self.canvas.clear();
         egui::CentralPanel::default().show(ctx, |ui| {
             let (response, painter) =
                 ui.allocate_painter(ui.available_size(), egui::Sense::click_and_drag());
+            self.canvas
+                .fit(response.rect, ctx.pixels_per_point(), self.render_scale);
 
-            let delta = response.drag_delta();
-            let strength = 8e-3;
-
-            if delta.y.abs() > 10.0 * strength {
-                self.scene.rotate_ox(delta.y * strength);
-            }
-            if delta.x.abs() > 10.0 * strength {
-                self.scene.rotate_oz(delta.x * strength);
-            }
+            self.handle_drag(ctx, &response);
 
             if self.visible.filling {
+                self.render_options.draw_normals = self.visible.normals;
                 self.scene
-                    .draw_fillings(&mut self.canvas, self.visible.normals);
+                    .draw_fillings(&mut self.canvas, &self.render_options);
                 self.canvas.draw(ctx, &painter);
             }
             if self.visible.mesh {
-                self.scene.draw_outlines(&painter);
+                self.scene.draw_outlines(&painter, &self.canvas);
             }
             if self.visible.polygon {
-                self.scene.draw_points(&painter);
+                self.scene.draw_points(&painter, &self.canvas);
+                let project = self.scene.viewport_projection(&self.canvas);
+                if let Some(h) = self.dragged.as_ref().and_then(|d| d.handles.first())
+                    && let Some(p) = project(self.scene.world_point(*h))
+                {
+                    painter.circle_stroke(p, 9.0, Stroke::new(2.0, Color32::YELLOW));
+                }
             }
             if self.visible.light_pos {
-                self.scene.draw_light_pos(&painter);
+                self.scene
+                    .draw_lights(&painter, &self.canvas, self.selected_light);
             }
         });
+        self.commit_params(ctx);
         ctx.request_repaint();
     }
 }
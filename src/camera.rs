@@ -0,0 +1,198 @@
+use std::f32::consts::FRAC_PI_2;
+
+use crate::{
+    point::{Point3, Vector3},
+    transform::Matrix4,
+};
+
+/// Perspective camera looking from `position` at `target`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera {
+    pub position: Point3,
+    pub target: Point3,
+    /// world direction shown upwards on the screen
+    pub up: Vector3,
+    /// vertical field of view in radians
+    pub fov: f32,
+    /// distance of the near clipping plane
+    pub near: f32,
+    /// distance of the far clipping plane
+    pub far: f32,
+}
+
+impl Default for Camera {
+    /// Looks down the z axis from where the surface fills most of an 800 px view
+    fn default() -> Self {
+        Self {
+            position: Point3::new(0.0, 0.0, 1500.0),
+            target: Point3::origin(),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            fov: 30.0f32.to_radians(),
+            near: 10.0,
+            far: 10000.0,
+        }
+    }
+}
+
+/// Rotates `v` by `angle` around the unit `axis` (Rodrigues' formula)
+fn rotate_around(v: Vector3, axis: Vector3, angle: f32) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    v * c + axis.cross(v) * s + axis * (axis.dot(v) * (1.0 - c))
+}
+
+impl Camera {
+    /// Unit vectors pointing right, up and forward from the camera
+    pub fn basis(&self) -> (Vector3, Vector3, Vector3) {
+        let forward = (self.target - self.position).normalized();
+        let right = forward.cross(self.up).normalized();
+        let up = right.cross(forward);
+        (right, up, forward)
+    }
+
+    /// Transform from world to view space, see [`Matrix4::look_at`]
+    pub(crate) fn view_matrix(&self) -> Matrix4 {
+        Matrix4::look_at(self.position, self.target, self.up)
+    }
+
+    /// Distance from the camera to `p` along the viewing direction
+    pub fn depth(&self, p: Point3) -> f32 {
+        let (_, _, forward) = self.basis();
+        (p - self.position).dot(forward)
+    }
+
+    /// Focal length in pixels for an image `height` pixels tall
+    fn focal(&self, height: f32) -> f32 {
+        height * 0.5 / (self.fov * 0.5).tan()
+    }
+
+    /// Projects `p` onto a `width` x `height` image, returns its pixel coordinates
+    /// (y pointing down) with z = 1 / depth, so that closer points have larger z,
+    /// or `None` if `p` lies outside the near and far planes
+    pub fn project(&self, p: Point3, width: f32, height: f32) -> Option<Point3> {
+        self.project_view(self.view_matrix().transform_point(p), width, height)
+    }
+
+    /// Same as [`Camera::project`] for a point already in view space
+    pub fn project_view(&self, v: Point3, width: f32, height: f32) -> Option<Point3> {
+        let depth = -v.z;
+        if !(self.near..=self.far).contains(&depth) {
+            return None;
+        }
+
+        let f = self.focal(height) / depth;
+        Some(Point3::new(
+            width * 0.5 + v.x * f,
+            height * 0.5 - v.y * f,
+            1.0 / depth,
+        ))
+    }
+
+    /// World length of a pixel at `depth` in an image `height` pixels tall
+    pub fn pixel_size(&self, depth: f32, height: f32) -> f32 {
+        depth / self.focal(height)
+    }
+
+    /// Orbits the camera around the target, `yaw` around the up direction and
+    /// `pitch` towards it, stopping just short of looking straight along it
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        let up = self.up.normalized();
+        let offset = rotate_around(self.position - self.target, up, -yaw);
+
+        let elevation = FRAC_PI_2 - offset.normalized().dot(up).clamp(-1.0, 1.0).acos();
+        let limit = FRAC_PI_2 - 1e-2;
+        let pitch = (elevation + pitch).clamp(-limit, limit) - elevation;
+        let right = offset.cross(up).normalized();
+        let offset = rotate_around(offset, right, pitch);
+
+        self.position = self.target + offset;
+    }
+
+    /// Moves the camera and its target by `dx`, `dy` pixels of an image
+    /// `height` pixels tall, measured at the target
+    pub fn pan(&mut self, dx: f32, dy: f32, height: f32) {
+        let (right, up, _) = self.basis();
+        let scale = self.pixel_size(self.depth(self.target), height);
+        let delta = (right * -dx + up * dy) * scale;
+        self.position += delta;
+        self.target += delta;
+    }
+
+    /// Scales the distance to the target by `factor`, keeping it past the near plane
+    pub fn zoom(&mut self, factor: f32) {
+        let offset = self.position - self.target;
+        let dist = offset.length();
+        let new_dist = (dist * factor).clamp(2.0 * self.near, 0.5 * self.far);
+        self.position = self.target + offset * (new_dist / dist);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Point3, b: Point3) {
+        assert!((a - b).length() < 1e-2, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn projects_onto_the_image() {
+        let camera = Camera::default();
+        let centre = camera.project(Point3::origin(), 800.0, 600.0).unwrap();
+        assert_close(centre, Point3::new(400.0, 300.0, 1.0 / 1500.0));
+
+        // the top edge of the field of view at the target
+        let top = 1500.0 * (camera.fov * 0.5).tan();
+        let p = camera
+            .project(Point3::new(top, top, 0.0), 800.0, 600.0)
+            .unwrap();
+        assert_close(p, Point3::new(700.0, 0.0, 1.0 / 1500.0));
+
+        assert!(
+            camera
+                .project(Point3::new(0.0, 0.0, 1495.0), 800.0, 600.0)
+                .is_none()
+        );
+        assert!(
+            camera
+                .project(Point3::new(0.0, 0.0, -9000.0), 800.0, 600.0)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn orbit_stops_short_of_the_up_direction() {
+        let mut camera = Camera::default();
+        camera.orbit(0.3, 10.0);
+        let offset = camera.position - camera.target;
+        assert!((offset.length() - 1500.0).abs() < 1e-2);
+        let elevation = FRAC_PI_2 - offset.normalized().dot(camera.up).acos();
+        assert!(elevation > 1.5 && elevation < FRAC_PI_2, "{elevation}");
+
+        camera.orbit(0.0, -20.0);
+        let offset = camera.position - camera.target;
+        let elevation = FRAC_PI_2 - offset.normalized().dot(camera.up).acos();
+        assert!(elevation < -1.5 && elevation > -FRAC_PI_2, "{elevation}");
+    }
+
+    #[test]
+    fn pan_moves_the_image_by_the_drag() {
+        let mut camera = Camera::default();
+        let p = Point3::new(100.0, -50.0, 0.0);
+        let before = camera.project(p, 800.0, 600.0).unwrap();
+        camera.pan(30.0, -20.0, 600.0);
+        let after = camera.project(p, 800.0, 600.0).unwrap();
+        assert_close(after, before + Vector3::new(30.0, -20.0, 0.0));
+        assert_eq!(camera.depth(camera.target), 1500.0);
+    }
+
+    #[test]
+    fn zoom_scales_the_distance_within_the_planes() {
+        let mut camera = Camera::default();
+        camera.zoom(0.5);
+        assert_close(camera.position, Point3::new(0.0, 0.0, 750.0));
+        camera.zoom(1e-6);
+        assert_close(camera.position, Point3::new(0.0, 0.0, 2.0 * camera.near));
+        camera.zoom(1e6);
+        assert_close(camera.position, Point3::new(0.0, 0.0, 0.5 * camera.far));
+    }
+}
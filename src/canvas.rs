@@ -1,26 +1,123 @@
 use core::f32;
+use std::ops::Range;
 
 use eframe::egui::{self, Context, Painter, Pos2, Rect, TextureOptions, pos2, vec2};
 
+use crate::{
+    color,
+    point::{Point3, Vector3},
+    render::{Antialiasing, ToneMapping},
+};
+
+/// Positions of the multisampling samples relative to the pixel centre,
+/// in 1/16 of a pixel (the standard 2x, 4x and 8x patterns)
+const SAMPLES_1: [(i32, i32); 1] = [(0, 0)];
+const SAMPLES_2: [(i32, i32); 2] = [(4, 4), (-4, -4)];
+const SAMPLES_4: [(i32, i32); 4] = [(-2, -6), (6, -2), (-6, 2), (2, 6)];
+const SAMPLES_8: [(i32, i32); 8] = [
+    (1, -3),
+    (-1, 3),
+    (5, 1),
+    (-3, -5),
+    (-5, 5),
+    (-7, -1),
+    (3, 7),
+    (7, -7),
+];
+
+/// Image drawn into by the rasteriser. Triangles are drawn into a grid of
+/// `scale` x `scale` raster pixels per image pixel (supersampling), each holding
+/// the linear HDR color and depth of several coverage samples (multisampling);
+/// [`Canvas::resolve`] averages them, tone maps and encodes them into the sRGB image.
 pub struct Canvas {
     width: usize,
     height: usize,
+    /// part of the viewport the image is shown in, in points
+    rect: Rect,
+    scale: usize,
+    offsets: &'static [(i32, i32)],
+    /// RGBA pixels of the resolved image, in sRGB
     buffer: Vec<u8>,
+    /// linear RGB colors of the samples, pixel by pixel of the raster grid,
+    /// with no upper bound
+    samples: Vec<[f32; 3]>,
+    /// 1 / depth of the samples, negative infinity where nothing was drawn
     depths: Vec<f32>,
     texture: Option<egui::TextureHandle>,
 }
 
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Self {
-        Self {
+        let mut canvas = Self {
             width,
             height,
-            buffer: vec![0; width * height * 4],
-            depths: vec![f32::NEG_INFINITY; width * height],
+            rect: Rect::from_min_size(Pos2::ZERO, vec2(width as f32, height as f32)),
+            scale: 1,
+            offsets: &SAMPLES_1,
+            buffer: Vec::new(),
+            samples: Vec::new(),
+            depths: Vec::new(),
             texture: None,
+        };
+        canvas.allocate();
+        canvas
+    }
+
+    fn allocate(&mut self) {
+        let (w, h) = self.raster_size();
+        let n = w * h * self.offsets.len();
+        self.buffer = vec![0; self.width * self.height * 4];
+        self.samples = vec![[0.0; 3]; n];
+        self.depths = vec![f32::NEG_INFINITY; n];
+    }
+
+    /// Switches the sampling of the canvas, clearing it if it changes.
+    /// The number of multisampling samples is rounded down to 1, 2, 4 or 8.
+    pub fn set_antialiasing(&mut self, antialiasing: Antialiasing) {
+        let (scale, offsets): (usize, &'static [(i32, i32)]) = match antialiasing {
+            Antialiasing::Off => (1, &SAMPLES_1),
+            Antialiasing::Supersampling(k) => (k.max(1), &SAMPLES_1),
+            Antialiasing::Multisampling(n) => match n {
+                0 | 1 => (1, &SAMPLES_1),
+                2 | 3 => (1, &SAMPLES_2),
+                4..=7 => (1, &SAMPLES_4),
+                _ => (1, &SAMPLES_8),
+            },
+        };
+        if scale != self.scale || offsets.len() != self.offsets.len() {
+            self.scale = scale;
+            self.offsets = offsets;
+            self.allocate();
         }
     }
 
+    /// Shows the canvas in `rect` of the viewport, resizing it (and clearing it
+    /// if the size changes) to `render_scale` pixels per physical pixel
+    pub fn fit(&mut self, rect: Rect, pixels_per_point: f32, render_scale: f32) {
+        let size = rect.size() * pixels_per_point * render_scale;
+        let width = (size.x.round() as usize).max(1);
+        let height = (size.y.round() as usize).max(1);
+        self.rect = rect;
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.texture = None;
+            self.allocate();
+        }
+    }
+
+    /// Part of the viewport the canvas is shown in, in points
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Position in the viewport of the image pixel `p`
+    pub fn to_viewport(&self, p: Pos2) -> Pos2 {
+        let size = self.rect.size();
+        let scale = vec2(size.x / self.width as f32, size.y / self.height as f32);
+        self.rect.min + p.to_vec2() * scale
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -29,36 +126,105 @@ impl Canvas {
         self.height
     }
 
-    pub fn put_pixel(&mut self, x: usize, y: usize, z: f32, rgba: [u8; 4]) {
-        if x >= self.width || y >= self.height {
+    /// Raster pixels per image pixel in each direction
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// Size of the grid the triangles are drawn into, `scale` times the image size
+    pub fn raster_size(&self) -> (usize, usize) {
+        (self.width * self.scale, self.height * self.scale)
+    }
+
+    /// RGBA pixels, row by row, as of the last [`Canvas::resolve`]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Draws the linear color `rgb` into every sample of the raster pixel (x, y)
+    /// closer than `z`
+    pub fn put_pixel(&mut self, x: usize, y: usize, z: f32, rgb: [f32; 3]) {
+        let (w, h) = self.raster_size();
+        if x >= w || y >= h {
             return;
         }
 
-        let idx = y * self.width + x;
-        if z > self.depths[idx] {
-            self.depths[idx] = z;
-            let bidx = idx * 4;
-            self.buffer[bidx..bidx + 4].copy_from_slice(&rgba);
+        let n = self.offsets.len();
+        let idx = (y * w + x) * n;
+        put_samples(
+            &mut self.samples[idx..idx + n],
+            &mut self.depths[idx..idx + n],
+            z,
+            rgb,
+        );
+    }
+
+    /// Splits the raster grid into bands of `rows` rows (the last one may be shorter),
+    /// which can be drawn into from different threads
+    pub fn tiles(&mut self, rows: usize) -> Vec<Tile<'_>> {
+        let (width, height) = self.raster_size();
+        let (scale, offsets) = (self.scale, self.offsets);
+        let n = offsets.len();
+        if self.depths.is_empty() {
+            return Vec::new();
         }
+        self.samples
+            .chunks_mut(width * rows * n)
+            .zip(self.depths.chunks_mut(width * rows * n))
+            .enumerate()
+            .map(|(i, (samples, depths))| Tile {
+                width,
+                height,
+                scale,
+                offsets,
+                y0: i * rows,
+                samples,
+                depths,
+                normals: Vec::new(),
+            })
+            .collect()
     }
 
-    pub fn clear(&mut self, rgba: impl Into<Option<[u8; 4]>>) {
-        match rgba.into() {
-            Some(ref c) => {
-                for px in self.buffer.chunks_exact_mut(4) {
-                    px.copy_from_slice(c);
+    /// Averages the samples of every pixel covered by the surface in linear space,
+    /// with the covered fraction as opacity, then compresses them with `tone_mapping`
+    /// after scaling by 2^`exposure` and encodes them as sRGB into the image
+    pub fn resolve(&mut self, tone_mapping: ToneMapping, exposure: f32) {
+        let (w, scale, n) = (self.width * self.scale, self.scale, self.offsets.len());
+        let count = (scale * scale * n) as f32;
+        let gain = exposure.exp2();
+        for (i, px) in self.buffer.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % self.width, i / self.width);
+            let mut sum = [0.0f32; 3];
+            let mut covered = 0;
+            for sy in y * scale..(y + 1) * scale {
+                let row = (sy * w + x * scale) * n;
+                let samples = &self.samples[row..row + scale * n];
+                for (s, &d) in samples.iter().zip(&self.depths[row..row + scale * n]) {
+                    if d == f32::NEG_INFINITY {
+                        continue;
+                    }
+                    for c in 0..3 {
+                        sum[c] += s[c];
+                    }
+                    covered += 1;
                 }
             }
-            None => self.buffer.fill(0),
+            if covered == 0 {
+                px.fill(0);
+                continue;
+            }
+            for c in 0..3 {
+                let linear = tone_mapping.apply(sum[c] / covered as f32 * gain);
+                px[c] = color::linear_to_srgb(linear);
+            }
+            px[3] = (covered as f32 / count * 255.0).round() as u8;
         }
-        self.depths.fill(f32::NEG_INFINITY);
     }
 
-    pub fn pos_from_screen(&self, pos: Pos2) -> Pos2 {
-        pos2(
-            pos.x - (self.width as f32) * 0.5,
-            (self.height as f32) * 0.5 - pos.y,
-        )
+    pub fn clear(&mut self) {
+        self.buffer.fill(0);
+        self.samples.fill([0.0; 3]);
+        self.depths.fill(f32::NEG_INFINITY);
     }
 
     pub fn draw(&mut self, ctx: &Context, painter: &Painter) {
@@ -76,15 +242,163 @@ impl Canvas {
 
         let tex = self.texture.as_ref().unwrap();
 
-        let size = vec2(self.width as f32, self.height as f32);
-
-        let used_sz = ctx.used_size();
-        let pos = (used_sz - size) * 0.5;
         painter.image(
             tex.id(),
-            Rect::from_min_size(pos.to_pos2(), size),
+            self.rect,
             Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
             eframe::egui::Color32::WHITE,
         );
     }
 }
+
+/// Depth tests and writes `rgb` into each of the samples
+fn put_samples(samples: &mut [[f32; 3]], depths: &mut [f32], z: f32, rgb: [f32; 3]) {
+    for (s, d) in samples.iter_mut().zip(depths) {
+        if z > *d {
+            *d = z;
+            *s = rgb;
+        }
+    }
+}
+
+/// Band of whole rows of the raster grid of a [`Canvas`], borrowing its part
+/// of the sample color and depth buffers
+pub struct Tile<'a> {
+    width: usize,
+    height: usize,
+    scale: usize,
+    offsets: &'static [(i32, i32)],
+    /// first row of the band
+    y0: usize,
+    samples: &'a mut [[f32; 3]],
+    depths: &'a mut [f32],
+    /// normal vectors at surface points, drawn over the canvas once all tiles are done
+    /// as they may cross into other tiles
+    pub normals: Vec<(Point3, Vector3)>,
+}
+
+impl Tile<'_> {
+    /// Width of the whole raster grid
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the whole raster grid
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Raster pixels per image pixel in each direction
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    /// Positions of the samples of a raster pixel relative to its centre,
+    /// in 1/16 of a pixel
+    pub fn sample_offsets(&self) -> &'static [(i32, i32)] {
+        self.offsets
+    }
+
+    /// Rows of the raster grid covered by the tile
+    pub fn rows(&self) -> Range<usize> {
+        self.y0..self.y0 + self.depths.len() / (self.width * self.offsets.len())
+    }
+
+    /// Same as [`Canvas::put_pixel`], `y` counts from the top of the grid and
+    /// pixels outside the tile are skipped
+    pub fn put_pixel(&mut self, x: usize, y: usize, z: f32, rgb: [f32; 3]) {
+        if x >= self.width || !self.rows().contains(&y) {
+            return;
+        }
+
+        let n = self.offsets.len();
+        let idx = ((y - self.y0) * self.width + x) * n;
+        put_samples(
+            &mut self.samples[idx..idx + n],
+            &mut self.depths[idx..idx + n],
+            z,
+            rgb,
+        );
+    }
+
+    /// Draws into the single `sample` of the raster pixel (x, y) if it is closer than `z`
+    pub fn put_sample(&mut self, x: usize, y: usize, sample: usize, z: f32, rgb: [f32; 3]) {
+        if x >= self.width || !self.rows().contains(&y) {
+            return;
+        }
+
+        let idx = ((y - self.y0) * self.width + x) * self.offsets.len() + sample;
+        put_samples(
+            &mut self.samples[idx..idx + 1],
+            &mut self.depths[idx..idx + 1],
+            z,
+            rgb,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: [f32; 3] = [1.0; 3];
+
+    /// Alpha and red channel of the image pixel (x, y) after resolving
+    fn resolved(canvas: &mut Canvas, x: usize, y: usize) -> (u8, u8) {
+        canvas.resolve(ToneMapping::Exposure, 20.0);
+        let px = &canvas.buffer()[(y * canvas.width() + x) * 4..][..4];
+        (px[3], px[0])
+    }
+
+    #[test]
+    fn supersampling_resolves_partial_coverage() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_antialiasing(Antialiasing::Supersampling(2));
+        assert_eq!(canvas.raster_size(), (4, 2));
+        // the left pixel is covered whole, the right one by the left half of its raster pixels
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1), (2, 0), (2, 1)] {
+            canvas.put_pixel(x, y, 1.0, WHITE);
+        }
+        assert_eq!(resolved(&mut canvas, 0, 0), (255, 255));
+        // the color is averaged over the covered samples only
+        assert_eq!(resolved(&mut canvas, 1, 0), (128, 255));
+        canvas.clear();
+        assert_eq!(resolved(&mut canvas, 0, 0), (0, 0));
+    }
+
+    #[test]
+    fn multisampling_resolves_partial_coverage() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set_antialiasing(Antialiasing::Multisampling(4));
+        assert_eq!(canvas.raster_size(), (2, 1));
+        {
+            let tile = &mut canvas.tiles(1)[0];
+            for s in 0..4 {
+                tile.put_sample(0, 0, s, 1.0, WHITE);
+            }
+            tile.put_sample(1, 0, 2, 1.0, WHITE);
+        }
+        assert_eq!(resolved(&mut canvas, 0, 0), (255, 255));
+        assert_eq!(resolved(&mut canvas, 1, 0), (64, 255));
+    }
+
+    #[test]
+    fn fits_the_viewport_in_physical_pixels() {
+        let mut canvas = Canvas::new(1, 1);
+        let rect = Rect::from_min_size(pos2(200.0, 10.0), vec2(400.0, 300.0));
+        canvas.fit(rect, 2.0, 1.0);
+        assert_eq!((canvas.width(), canvas.height()), (800, 600));
+        canvas.fit(rect, 2.0, 0.5);
+        assert_eq!((canvas.width(), canvas.height()), (400, 300));
+        canvas.set_antialiasing(Antialiasing::Supersampling(3));
+        assert_eq!(canvas.raster_size(), (1200, 900));
+        canvas.fit(rect, 1.5, 0.25);
+        assert_eq!((canvas.width(), canvas.height()), (150, 113));
+        assert_eq!(canvas.rect(), rect);
+
+        canvas.fit(rect, 2.0, 0.5);
+        assert_eq!(canvas.to_viewport(pos2(0.0, 0.0)), pos2(200.0, 10.0));
+        assert_eq!(canvas.to_viewport(pos2(400.0, 300.0)), pos2(600.0, 310.0));
+        assert_eq!(canvas.to_viewport(pos2(100.0, 150.0)), pos2(300.0, 160.0));
+    }
+}
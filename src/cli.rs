@@ -0,0 +1,618 @@
+use std::path::{Path, PathBuf};
+
+use bezier_surface::{
+    Antialiasing, Attenuation, Color, Coloring, LightKind, Rasterizer, RenderOptions, Scene,
+    ShadingModel, ShadowOptions, TextureFilter, ToneMapping, Vector3,
+};
+
+pub const USAGE: &str = "\
+usage: bezier-surface [POINTS_FILE] [OPTIONS]
+
+Without --render opens the interactive window, the remaining options set its initial state.
+
+options:
+  --render OUT.png        render a single frame to OUT.png without opening a window
+  --size WxH              size of the rendered image (default 800x800)
+  --rot-ox DEG            rotation around the OX axis
+  --rot-oy DEG            rotation around the OY axis, applied after OX
+  --rot-oz DEG            rotation around the OZ axis, applied last
+  --resolution N          mesh resolution, 2..=50
+  --ka F                  ambient fraction, 0..=1
+  --kd F                  diffuse fraction, 0..=1
+  --ks F                  specular fraction, 0..=1
+  --m N                   specular exponent, positive
+  --shading MODEL         phong (default), blinn-phong or cook-torrance
+  --metallic F            metalness of the cook-torrance model, 0..=1
+  --roughness F           roughness of the cook-torrance model, 0..=1
+  --color R,G,B           surface color, components in 0..=1
+  --texture PATH          surface texture
+  --normal-map PATH       normal map
+  --texture-filter F      texture filtering, nearest, bilinear (default), trilinear
+                          or anisotropic
+  --normal-map-filter F   normal map filtering, as --texture-filter
+  --ambient R,G,B         ambient light color, components in 0..=1
+  --light-color R,G,B     light color, components in 0..=1
+  --light-z Z             height of the light
+  --light-t T             position of the light along its path, 0..=1
+  --light-kind KIND       point, directional or spot
+  --light-dir X,Y,Z       direction of a directional light or spotlight
+  --light-intensity I     light intensity
+  --light-attenuation C,L,Q
+                          constant, linear and quadratic attenuation with the distance
+  --spot-angles IN,OUT    inner and outer spotlight cone angles in degrees
+                          (the --light-* options change the first light of the scene)
+  --threads N             number of rendering threads (default: all cores)
+  --rasterizer NAME       triangle filling, scanline (default) or edge
+  --ssaa K                supersample K x K pixels per pixel, K in 1..=4
+  --msaa N                multisample with N samples per pixel, 1, 2, 4 or 8
+  --shadow-res N          size of the shadow map faces in texels (default: 1024)
+  --shadow-bias B         nonnegative shadow depth bias in world units (default: 2)
+  --no-shadows            light the surface without shadows
+  --tone-map OP           tone mapping, exposure, reinhard or aces (default)
+  --exposure EV           exposure in stops (default: 0)
+  --normals               draw normal vectors
+  --affine                interpolate linearly in screen space, without perspective correction
+  -h, --help              print this message";
+
+/// Options followed by a value
+const VALUE_FLAGS: &[&str] = &[
+    "--render",
+    "--size",
+    "--rot-ox",
+    "--rot-oy",
+    "--rot-oz",
+    "--resolution",
+    "--ka",
+    "--kd",
+    "--ks",
+    "--m",
+    "--shading",
+    "--metallic",
+    "--roughness",
+    "--color",
+    "--texture",
+    "--normal-map",
+    "--texture-filter",
+    "--normal-map-filter",
+    "--ambient",
+    "--light-color",
+    "--light-z",
+    "--light-t",
+    "--light-kind",
+    "--light-dir",
+    "--light-intensity",
+    "--light-attenuation",
+    "--spot-angles",
+    "--threads",
+    "--rasterizer",
+    "--ssaa",
+    "--msaa",
+    "--shadow-res",
+    "--shadow-bias",
+    "--tone-map",
+    "--exposure",
+];
+
+#[derive(Default)]
+pub struct Args {
+    pub points: Option<String>,
+    pub render: Option<PathBuf>,
+    pub size: Option<(usize, usize)>,
+    pub rot_ox: Option<f32>,
+    pub rot_oy: Option<f32>,
+    pub rot_oz: Option<f32>,
+    pub resolution: Option<usize>,
+    pub ka: Option<f32>,
+    pub kd: Option<f32>,
+    pub ks: Option<f32>,
+    pub m: Option<i32>,
+    pub shading: Option<ShadingModel>,
+    pub metallic: Option<f32>,
+    pub roughness: Option<f32>,
+    pub color: Option<[f32; 3]>,
+    pub texture: Option<PathBuf>,
+    pub normal_map: Option<PathBuf>,
+    pub texture_filter: Option<TextureFilter>,
+    pub normal_map_filter: Option<TextureFilter>,
+    pub ambient: Option<[f32; 3]>,
+    pub light_color: Option<[f32; 3]>,
+    pub light_z: Option<f32>,
+    pub light_t: Option<f32>,
+    pub light_kind: Option<LightKind>,
+    pub light_dir: Option<Vector3>,
+    pub light_intensity: Option<f32>,
+    pub light_attenuation: Option<Attenuation>,
+    /// inner and outer angles in degrees
+    pub spot_angles: Option<(f32, f32)>,
+    pub threads: Option<usize>,
+    pub rasterizer: Option<Rasterizer>,
+    pub antialiasing: Option<Antialiasing>,
+    pub shadow_res: Option<usize>,
+    pub shadow_bias: Option<f32>,
+    pub no_shadows: bool,
+    pub tone_mapping: Option<ToneMapping>,
+    pub exposure: Option<f32>,
+    pub normals: bool,
+    pub affine: bool,
+    pub help: bool,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("invalid value for {flag} '{value}', {e}"))
+}
+
+fn parse_fraction(flag: &str, value: &str) -> Result<f32, String> {
+    let f = parse_number(flag, value)?;
+    if !(0.0..=1.0).contains(&f) {
+        return Err(format!("{flag} must be in 0..=1"));
+    }
+    Ok(f)
+}
+
+fn parse_list(flag: &str, value: &str) -> Result<Vec<f32>, String> {
+    value
+        .split(',')
+        .map(|c| parse_number::<f32>(flag, c.trim()))
+        .collect()
+}
+
+fn parse_color(flag: &str, value: &str) -> Result<[f32; 3], String> {
+    let comps = parse_list(flag, value)?;
+    match comps[..] {
+        [r, g, b] if comps.iter().all(|c| (0.0..=1.0).contains(c)) => Ok([r, g, b]),
+        _ => Err(format!("{flag} expects R,G,B with components in 0..=1")),
+    }
+}
+
+fn parse_filter(flag: &str, value: &str) -> Result<TextureFilter, String> {
+    match value {
+        "nearest" => Ok(TextureFilter::Nearest),
+        "bilinear" => Ok(TextureFilter::Bilinear),
+        "trilinear" => Ok(TextureFilter::Trilinear),
+        "anisotropic" => Ok(TextureFilter::Anisotropic),
+        _ => Err(format!(
+            "{flag} must be nearest, bilinear, trilinear or anisotropic"
+        )),
+    }
+}
+
+fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let (w, h) = value
+        .split_once('x')
+        .ok_or(format!("--size expects WxH, got '{value}'"))?;
+    let (w, h) = (parse_number("--size", w)?, parse_number("--size", h)?);
+    if w == 0 || h == 0 {
+        return Err("--size must be positive".into());
+    }
+    Ok((w, h))
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                if parsed.points.replace(arg).is_some() {
+                    return Err("only one points file may be given".into());
+                }
+                continue;
+            }
+
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    parsed.help = true;
+                    continue;
+                }
+                "--normals" => {
+                    parsed.normals = true;
+                    continue;
+                }
+                "--affine" => {
+                    parsed.affine = true;
+                    continue;
+                }
+                "--no-shadows" => {
+                    parsed.no_shadows = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let flag = arg.as_str();
+            if !VALUE_FLAGS.contains(&flag) {
+                return Err(format!("unknown option {arg}\n\n{USAGE}"));
+            }
+            let value = args.next().ok_or(format!("missing value for {arg}"))?;
+            match flag {
+                "--render" => parsed.render = Some(value.into()),
+                "--size" => parsed.size = Some(parse_size(&value)?),
+                "--rot-ox" => parsed.rot_ox = Some(parse_number(flag, &value)?),
+                "--rot-oy" => parsed.rot_oy = Some(parse_number(flag, &value)?),
+                "--rot-oz" => parsed.rot_oz = Some(parse_number(flag, &value)?),
+                "--resolution" => {
+                    let n = parse_number(flag, &value)?;
+                    if !(2..=50).contains(&n) {
+                        return Err("--resolution must be in 2..=50".into());
+                    }
+                    parsed.resolution = Some(n);
+                }
+                "--ka" => parsed.ka = Some(parse_fraction(flag, &value)?),
+                "--kd" => parsed.kd = Some(parse_fraction(flag, &value)?),
+                "--ks" => parsed.ks = Some(parse_fraction(flag, &value)?),
+                "--m" => {
+                    let m = parse_number(flag, &value)?;
+                    if m <= 0 {
+                        return Err("--m must be positive".into());
+                    }
+                    parsed.m = Some(m);
+                }
+                "--shading" => {
+                    parsed.shading = Some(match value.as_str() {
+                        "phong" => ShadingModel::Phong,
+                        "blinn-phong" => ShadingModel::BlinnPhong,
+                        "cook-torrance" => ShadingModel::cook_torrance(),
+                        _ => {
+                            return Err(
+                                "--shading must be phong, blinn-phong or cook-torrance".into()
+                            );
+                        }
+                    });
+                }
+                "--metallic" | "--roughness" => {
+                    let f = parse_fraction(flag, &value)?;
+                    if flag == "--metallic" {
+                        parsed.metallic = Some(f);
+                    } else {
+                        parsed.roughness = Some(f);
+                    }
+                }
+                "--color" => parsed.color = Some(parse_color(flag, &value)?),
+                "--texture" => parsed.texture = Some(value.into()),
+                "--normal-map" => parsed.normal_map = Some(value.into()),
+                "--texture-filter" => parsed.texture_filter = Some(parse_filter(flag, &value)?),
+                "--normal-map-filter" => {
+                    parsed.normal_map_filter = Some(parse_filter(flag, &value)?);
+                }
+                "--ambient" => parsed.ambient = Some(parse_color(flag, &value)?),
+                "--light-color" => parsed.light_color = Some(parse_color(flag, &value)?),
+                "--light-z" => parsed.light_z = Some(parse_number(flag, &value)?),
+                "--light-t" => parsed.light_t = Some(parse_fraction(flag, &value)?),
+                "--light-kind" => {
+                    parsed.light_kind = Some(match value.as_str() {
+                        "point" => LightKind::Point,
+                        "directional" => LightKind::Directional,
+                        "spot" => LightKind::spot(),
+                        _ => return Err("--light-kind must be point, directional or spot".into()),
+                    });
+                }
+                "--light-dir" => {
+                    let dir = parse_list(flag, &value)?;
+                    match dir[..] {
+                        [x, y, z] if Vector3::new(x, y, z).length() > 1e-6 => {
+                            parsed.light_dir = Some(Vector3::new(x, y, z).normalized());
+                        }
+                        _ => return Err("--light-dir expects a nonzero X,Y,Z vector".into()),
+                    }
+                }
+                "--light-intensity" => {
+                    let i: f32 = parse_number(flag, &value)?;
+                    if !(0.0..=f32::MAX).contains(&i) {
+                        return Err("--light-intensity must be nonnegative".into());
+                    }
+                    parsed.light_intensity = Some(i);
+                }
+                "--light-attenuation" => match parse_list(flag, &value)?[..] {
+                    [constant, linear, quadratic]
+                        if [constant, linear, quadratic].iter().all(|&c| c >= 0.0)
+                            && constant + linear + quadratic > 0.0 =>
+                    {
+                        parsed.light_attenuation = Some(Attenuation {
+                            constant,
+                            linear,
+                            quadratic,
+                        });
+                    }
+                    _ => {
+                        return Err(
+                            "--light-attenuation expects nonnegative C,L,Q, not all zero".into(),
+                        );
+                    }
+                },
+                "--spot-angles" => match parse_list(flag, &value)?[..] {
+                    [inner, outer] if 0.0 <= inner && inner <= outer && outer <= 90.0 => {
+                        parsed.spot_angles = Some((inner, outer));
+                    }
+                    _ => {
+                        return Err("--spot-angles expects IN,OUT with 0 <= IN <= OUT <= 90".into());
+                    }
+                },
+                "--threads" => {
+                    let n = parse_number(flag, &value)?;
+                    if n == 0 {
+                        return Err("--threads must be positive".into());
+                    }
+                    parsed.threads = Some(n);
+                }
+                "--rasterizer" => {
+                    parsed.rasterizer = Some(match value.as_str() {
+                        "scanline" => Rasterizer::Scanline,
+                        "edge" => Rasterizer::EdgeFunction,
+                        _ => return Err("--rasterizer must be scanline or edge".into()),
+                    });
+                }
+                "--ssaa" | "--msaa" => {
+                    if parsed.antialiasing.is_some() {
+                        return Err("--ssaa and --msaa cannot be combined".into());
+                    }
+                    let n = parse_number(flag, &value)?;
+                    parsed.antialiasing = Some(if flag == "--ssaa" {
+                        if !(1..=4).contains(&n) {
+                            return Err("--ssaa must be in 1..=4".into());
+                        }
+                        Antialiasing::Supersampling(n)
+                    } else {
+                        if ![1, 2, 4, 8].contains(&n) {
+                            return Err("--msaa must be 1, 2, 4 or 8".into());
+                        }
+                        Antialiasing::Multisampling(n)
+                    });
+                }
+                "--shadow-res" => {
+                    let n = parse_number(flag, &value)?;
+                    if !(16..=8192).contains(&n) {
+                        return Err("--shadow-res must be in 16..=8192".into());
+                    }
+                    parsed.shadow_res = Some(n);
+                }
+                "--shadow-bias" => {
+                    let b: f32 = parse_number(flag, &value)?;
+                    if !(0.0..=f32::MAX).contains(&b) {
+                        return Err("--shadow-bias must be nonnegative".into());
+                    }
+                    parsed.shadow_bias = Some(b);
+                }
+                "--tone-map" => {
+                    parsed.tone_mapping = Some(match value.as_str() {
+                        "exposure" => ToneMapping::Exposure,
+                        "reinhard" => ToneMapping::Reinhard,
+                        "aces" => ToneMapping::AcesFilmic,
+                        _ => return Err("--tone-map must be exposure, reinhard or aces".into()),
+                    });
+                }
+                "--exposure" => parsed.exposure = Some(parse_number(flag, &value)?),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Applies the scene options to a freshly loaded scene
+    pub fn apply(&self, scene: &mut Scene) -> Result<(), String> {
+        if self.rot_ox.is_some() || self.rot_oy.is_some() || self.rot_oz.is_some() {
+            let (ox, oy, oz) = scene.rotation();
+            scene.set_rotation(
+                self.rot_ox.map_or(ox, f32::to_radians),
+                self.rot_oy.map_or(oy, f32::to_radians),
+                self.rot_oz.map_or(oz, f32::to_radians),
+            );
+        }
+        if let Some(n) = self.resolution {
+            scene.set_mesh_resolution(n);
+        }
+
+        let material = &mut scene.material;
+        material.ka = self.ka.unwrap_or(material.ka);
+        material.kd = self.kd.unwrap_or(material.kd);
+        material.ks = self.ks.unwrap_or(material.ks);
+        material.m = self.m.unwrap_or(material.m);
+        material.shading = self.shading.unwrap_or(material.shading);
+        if self.metallic.is_some() || self.roughness.is_some() {
+            let ShadingModel::CookTorrance {
+                metallic,
+                roughness,
+            } = &mut material.shading
+            else {
+                return Err(
+                    "--metallic and --roughness apply to the cook-torrance model only".into(),
+                );
+            };
+            *metallic = self.metallic.unwrap_or(*metallic);
+            *roughness = self.roughness.unwrap_or(*roughness);
+        }
+        if let Some(c) = self.color {
+            scene.set_material_color(c);
+        }
+        if let Some(path) = &self.texture {
+            scene.set_texture(path.clone())?;
+        }
+        if let Some(path) = &self.normal_map {
+            scene.set_normal_map(path.clone())?;
+        }
+        if let Some(filter) = self.texture_filter {
+            let Coloring::Texture(t) = &mut scene.material.coloring else {
+                return Err("--texture-filter needs a textured surface".into());
+            };
+            t.filter = filter;
+        }
+        if let Some(filter) = self.normal_map_filter {
+            let Some(t) = &mut scene.material.normal_map else {
+                return Err("--normal-map-filter needs a normal map".into());
+            };
+            t.filter = filter;
+        }
+        if let Some(c) = self.ambient {
+            scene.set_ambient_color(c);
+        }
+
+        let light_options = self.light_color.is_some()
+            || self.light_z.is_some()
+            || self.light_t.is_some()
+            || self.light_kind.is_some()
+            || self.light_dir.is_some()
+            || self.light_intensity.is_some()
+            || self.light_attenuation.is_some()
+            || self.spot_angles.is_some();
+        if !light_options {
+            return Ok(());
+        }
+        let light = scene
+            .lights
+            .first_mut()
+            .ok_or("the scene has no light to set the --light-* options of")?;
+        if let Some(c) = self.light_color {
+            light.color = Color::from_slice(c);
+        }
+        if let Some(z) = self.light_z {
+            light.pos.z = z;
+        }
+        if let Some(kind) = self.light_kind {
+            light.kind = kind;
+        }
+        if let Some(dir) = self.light_dir {
+            light.dir = dir;
+        }
+        if let Some(i) = self.light_intensity {
+            light.intensity = i;
+        }
+        if let Some(a) = self.light_attenuation {
+            light.attenuation = a;
+        }
+        if let Some((inner, outer)) = self.spot_angles {
+            let LightKind::Spot { .. } = light.kind else {
+                return Err("--spot-angles apply to spotlights only".into());
+            };
+            light.kind = LightKind::Spot {
+                inner: inner.to_radians(),
+                outer: outer.to_radians(),
+            };
+        }
+        if let Some(t) = self.light_t {
+            light.is_animating = false;
+            light.place_on_orbit(t);
+        }
+        Ok(())
+    }
+}
+
+/// Rasterises a single frame of the scene and saves it as an image
+pub fn render(scene: &mut Scene, args: &Args, out: &Path) -> Result<(), String> {
+    let (width, height) = args.size.unwrap_or((800, 800));
+    let defaults = RenderOptions::default();
+    let options = RenderOptions {
+        draw_normals: args.normals,
+        perspective_correct: !args.affine,
+        threads: args.threads.unwrap_or(defaults.threads),
+        rasterizer: args.rasterizer.unwrap_or(defaults.rasterizer),
+        antialiasing: args.antialiasing.unwrap_or(defaults.antialiasing),
+        shadows: ShadowOptions {
+            enabled: !args.no_shadows,
+            resolution: args.shadow_res.unwrap_or(defaults.shadows.resolution),
+            bias: args.shadow_bias.unwrap_or(defaults.shadows.bias),
+        },
+        tone_mapping: args.tone_mapping.unwrap_or(defaults.tone_mapping),
+        exposure: args.exposure.unwrap_or(defaults.exposure),
+    };
+
+    // animated lights are placed on their orbit, the others keep their positions
+    scene.advance_light_animations(0.0);
+
+    let mut buffer = vec![0; width * height * 4];
+    bezier_surface::render(scene, &options, &mut buffer, width, height);
+
+    image::save_buffer(
+        out,
+        &buffer,
+        width as u32,
+        height as u32,
+        image::ExtendedColorType::Rgba8,
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        for flag in ["--ka", "--kd", "--ks", "--light-t"] {
+            assert!(parse(&[flag, "0.5"]).is_ok());
+            let err = parse(&[flag, "1.5"]).err().unwrap();
+            assert_eq!(err, format!("{flag} must be in 0..=1"));
+            assert!(parse(&[flag, "-0.1"]).is_err());
+            assert!(parse(&[flag, "NaN"]).is_err());
+        }
+        assert_eq!(parse(&["--m", "20"]).unwrap().m, Some(20));
+        assert!(parse(&["--m", "0"]).is_err());
+        assert!(parse(&["--m", "-3"]).is_err());
+        assert_eq!(
+            parse(&["--shadow-bias", "0"]).unwrap().shadow_bias,
+            Some(0.0)
+        );
+        assert!(parse(&["--shadow-bias", "-1"]).is_err());
+    }
+
+    #[test]
+    fn reports_flag_errors() {
+        assert!(
+            parse(&["--bogus"])
+                .err()
+                .unwrap()
+                .starts_with("unknown option --bogus")
+        );
+        assert_eq!(parse(&["--kd"]).err().unwrap(), "missing value for --kd");
+        let err = parse(&["--kd", "much"]).err().unwrap();
+        assert!(err.starts_with("invalid value for --kd 'much'"), "{err}");
+        assert!(parse(&["--size", "800"]).is_err());
+        assert!(parse(&["--resolution", "1"]).is_err());
+    }
+
+    #[test]
+    fn renders_without_a_window() {
+        let mut scene = Scene::from_file(None).unwrap();
+        let args = parse(&["--size", "64x48", "--kd", "0.7", "--light-t", "0.25"]).unwrap();
+        args.apply(&mut scene).unwrap();
+
+        let out = std::env::temp_dir().join(format!("bezier-surface-{}.png", std::process::id()));
+        let rendered = render(&mut scene, &args, &out);
+        let image = image::open(&out);
+        let _ = std::fs::remove_file(&out);
+        rendered.unwrap();
+        let image = image.unwrap().into_rgba8();
+        assert_eq!(image.dimensions(), (64, 48));
+        assert!(image.pixels().any(|px| px[3] == 255));
+    }
+
+    #[test]
+    fn parses_light_attenuation() {
+        let args = parse(&["--light-attenuation", "1,0.01,0.0001"]).unwrap();
+        assert_eq!(
+            args.light_attenuation,
+            Some(Attenuation {
+                constant: 1.0,
+                linear: 0.01,
+                quadratic: 0.0001
+            })
+        );
+        assert!(parse(&["--light-attenuation", "0,0,1e-5"]).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_light_attenuation() {
+        assert!(parse(&["--light-attenuation", "0,0,0"]).is_err());
+        assert!(parse(&["--light-attenuation", "1,-1,0"]).is_err());
+        assert!(parse(&["--light-attenuation", "1,0"]).is_err());
+    }
+}
@@ -1,4 +1,5 @@
-#[derive(Debug, Clone, Copy)]
+/// Linear RGB color with components in 0..=1
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color {
     r: f32,
     g: f32,
@@ -33,3 +34,48 @@ impl Color {
     }
 }
 
+/// Decodes an sRGB component in 0..=1 into linear space
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear component, clamped to 0..=1, as 8 bit sRGB
+pub fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(i as f32 / 255.0)), i);
+        }
+    }
+
+    #[test]
+    fn encodes_mid_grey() {
+        assert_eq!(linear_to_srgb(0.5), 188);
+        assert!((srgb_to_linear(188.0 / 255.0) - 0.5).abs() < 5e-3);
+        assert_eq!(linear_to_srgb(0.0), 0);
+        assert_eq!(linear_to_srgb(1.0), 255);
+    }
+
+    #[test]
+    fn clamps_out_of_range() {
+        assert_eq!(linear_to_srgb(-0.5), 0);
+        assert_eq!(linear_to_srgb(7.0), 255);
+    }
+}
@@ -0,0 +1,351 @@
+use std::io::{self, Cursor, Write};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde_json::{Value, json};
+
+use crate::{
+    material::{Coloring, Material, ShadingModel},
+    mesh::Mesh,
+    texture::Texture,
+};
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const LINEAR: u32 = 9729;
+const CLAMP_TO_EDGE: u32 = 33071;
+
+/// Single binary buffer holding geometry and images, referenced through buffer views
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    views: Vec<Value>,
+}
+
+impl Buffer {
+    /// Appends `bytes` as a new buffer view and returns its index
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+}
+
+fn floats_to_bytes(floats: &[f32]) -> Vec<u8> {
+    floats.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+fn encode_png(texture: &Texture) -> io::Result<Vec<u8>> {
+    let img = image::RgbaImage::from_raw(texture.width, texture.height, texture.data.clone())
+        .ok_or_else(|| io::Error::other("texture size does not match its data"))?;
+    let mut png = Cursor::new(Vec::new());
+    img.write_to(&mut png, image::ImageFormat::Png)
+        .map_err(io::Error::other)?;
+    Ok(png.into_inner())
+}
+
+/// Writes the Cook-Torrance material as it is, and approximates the Phong ones
+/// with a metallic-roughness PBR material: the base color is scaled by `kd`, `ks`
+/// becomes the KHR_materials_specular factor and the specular exponent is mapped
+/// to roughness through the Beckmann distribution, alpha = sqrt(2 / (m + 2))
+/// with roughness = sqrt(alpha)
+fn pbr_material(
+    material: &Material,
+    buffer: &mut Buffer,
+    images: &mut Vec<Value>,
+    textures: &mut Vec<Value>,
+) -> io::Result<Value> {
+    let mut add_texture = |texture: &Texture| -> io::Result<usize> {
+        let view = buffer.push_view(&encode_png(texture)?, None);
+        images.push(json!({ "bufferView": view, "mimeType": "image/png" }));
+        textures.push(json!({ "sampler": 0, "source": images.len() - 1 }));
+        Ok(textures.len() - 1)
+    };
+
+    let (kd, ks, metallic, roughness) = match material.shading {
+        ShadingModel::Phong | ShadingModel::BlinnPhong => {
+            let alpha = (2.0 / (material.m as f32 + 2.0)).sqrt();
+            (material.kd, material.ks, 0.0, alpha.sqrt())
+        }
+        ShadingModel::CookTorrance {
+            metallic,
+            roughness,
+        } => (1.0, 1.0, metallic, roughness),
+    };
+    let mut pbr = json!({
+        "metallicFactor": metallic,
+        "roughnessFactor": roughness,
+    });
+    match &material.coloring {
+        Coloring::Solid(c) => {
+            pbr["baseColorFactor"] = json!([c.r() * kd, c.g() * kd, c.b() * kd, 1.0]);
+        }
+        Coloring::Texture(t) => {
+            pbr["baseColorFactor"] = json!([kd, kd, kd, 1.0]);
+            pbr["baseColorTexture"] = json!({ "index": add_texture(t)? });
+        }
+    }
+
+    let mut gltf_material = json!({
+        "name": "surface",
+        "pbrMetallicRoughness": pbr,
+        "doubleSided": true,
+        "extensions": {
+            "KHR_materials_specular": { "specularFactor": ks }
+        },
+    });
+    if let Some(t) = &material.normal_map {
+        gltf_material["normalTexture"] = json!({ "index": add_texture(t)? });
+    }
+    Ok(gltf_material)
+}
+
+/// Writes the mesh together with its material as glTF 2.0, either as a
+/// self-contained `.gltf` (buffer embedded as a data URI) or as a binary `.glb`
+pub fn write_gltf(
+    mesh: &Mesh,
+    material: &Material,
+    binary: bool,
+    w: &mut impl Write,
+) -> io::Result<()> {
+    let indexed = mesh.indexed();
+
+    let mut positions = Vec::with_capacity(indexed.vertices.len() * 3);
+    let mut normals = Vec::with_capacity(indexed.vertices.len() * 3);
+    let mut tangents = Vec::with_capacity(indexed.vertices.len() * 4);
+    let mut uvs = Vec::with_capacity(indexed.vertices.len() * 2);
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for v in &indexed.vertices {
+        let p = v.pos();
+        for (k, c) in [p.x, p.y, p.z].into_iter().enumerate() {
+            min[k] = min[k].min(c);
+            max[k] = max[k].max(c);
+        }
+        positions.extend([p.x, p.y, p.z]);
+
+        let n = v.normal();
+        normals.extend([n.x, n.y, n.z]);
+
+        // Gram-Schmidt on the u derivative, handedness chosen so that
+        // cross(normal, tangent) * w follows the v derivative
+        let pu = v.pu();
+        let t = (pu - n * n.dot(pu)).normalized();
+        let handedness = if n.cross(t).dot(v.pv()) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        tangents.extend([t.x, t.y, t.z, handedness]);
+
+        // glTF puts the origin of texture coordinates in the top left corner
+        let (s, t) = v.uv();
+        uvs.extend([s, 1.0 - t]);
+    }
+    let indices: Vec<u8> = indexed
+        .triangles
+        .iter()
+        .flatten()
+        .flat_map(|&i| (i as u32).to_le_bytes())
+        .collect();
+
+    let mut buffer = Buffer::default();
+    let count = indexed.vertices.len();
+    let mut accessors = Vec::new();
+    let mut accessor = |view: usize, kind: &str, component: u32, count: usize| {
+        accessors.push(json!({
+            "bufferView": view,
+            "componentType": component,
+            "count": count,
+            "type": kind,
+        }));
+        accessors.len() - 1
+    };
+
+    let view = buffer.push_view(&floats_to_bytes(&positions), Some(ARRAY_BUFFER));
+    let position = accessor(view, "VEC3", FLOAT, count);
+    let view = buffer.push_view(&floats_to_bytes(&normals), Some(ARRAY_BUFFER));
+    let normal = accessor(view, "VEC3", FLOAT, count);
+    let view = buffer.push_view(&floats_to_bytes(&tangents), Some(ARRAY_BUFFER));
+    let tangent = accessor(view, "VEC4", FLOAT, count);
+    let view = buffer.push_view(&floats_to_bytes(&uvs), Some(ARRAY_BUFFER));
+    let texcoord = accessor(view, "VEC2", FLOAT, count);
+    let view = buffer.push_view(&indices, Some(ELEMENT_ARRAY_BUFFER));
+    let index = accessor(view, "SCALAR", UNSIGNED_INT, indexed.triangles.len() * 3);
+    accessors[position]["min"] = json!(min);
+    accessors[position]["max"] = json!(max);
+
+    let mut images = Vec::new();
+    let mut textures = Vec::new();
+    let material = pbr_material(material, &mut buffer, &mut images, &mut textures)?;
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "bezier-surface" },
+        "extensionsUsed": ["KHR_materials_specular"],
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "name": "surface", "mesh": 0 }],
+        "meshes": [{
+            "name": "surface",
+            "primitives": [{
+                "attributes": {
+                    "POSITION": position,
+                    "NORMAL": normal,
+                    "TANGENT": tangent,
+                    "TEXCOORD_0": texcoord,
+                },
+                "indices": index,
+                "material": 0,
+            }],
+        }],
+        "materials": [material],
+        "accessors": accessors,
+        "bufferViews": buffer.views,
+        "buffers": [{ "byteLength": buffer.data.len() }],
+    });
+    if !textures.is_empty() {
+        gltf["samplers"] = json!([{
+            "magFilter": LINEAR,
+            "minFilter": LINEAR,
+            "wrapS": CLAMP_TO_EDGE,
+            "wrapT": CLAMP_TO_EDGE,
+        }]);
+        gltf["images"] = json!(images);
+        gltf["textures"] = json!(textures);
+    }
+
+    if binary {
+        write_glb(&gltf, buffer.data, w)?;
+    } else {
+        gltf["buffers"][0]["uri"] = json!(format!(
+            "data:application/octet-stream;base64,{}",
+            STANDARD.encode(&buffer.data)
+        ));
+        serde_json::to_writer_pretty(&mut *w, &gltf)?;
+    }
+    w.flush()
+}
+
+/// Binary container: a 12 byte header followed by the JSON and BIN chunks,
+/// each padded to a multiple of 4 bytes
+fn write_glb(gltf: &Value, mut bin: Vec<u8>, w: &mut impl Write) -> io::Result<()> {
+    let mut json = serde_json::to_vec(gltf)?;
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    let total = 12 + 8 + json.len() + 8 + bin.len();
+    w.write_all(b"glTF")?;
+    w.write_all(&2u32.to_le_bytes())?;
+    w.write_all(&(total as u32).to_le_bytes())?;
+
+    w.write_all(&(json.len() as u32).to_le_bytes())?;
+    w.write_all(b"JSON")?;
+    w.write_all(&json)?;
+
+    w.write_all(&(bin.len() as u32).to_le_bytes())?;
+    w.write_all(b"BIN\0")?;
+    w.write_all(&bin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::BezierSurface;
+
+    fn flat_mesh() -> Mesh {
+        let patch: BezierSurface = "1 1\n0 0 0\n2 0 0\n0 2 0\n2 2 0\n".parse().unwrap();
+        Mesh::from_patches(&[patch], 3)
+    }
+
+    fn write(material: &Material, binary: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_gltf(&flat_mesh(), material, binary, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn writes_embedded_gltf() {
+        let material = Material::default();
+        let gltf: Value = serde_json::from_slice(&write(&material, false)).unwrap();
+        assert_eq!(gltf["asset"]["version"], "2.0");
+
+        let primitive = &gltf["meshes"][0]["primitives"][0];
+        let position =
+            &gltf["accessors"][primitive["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(position["count"], 9);
+        assert_eq!(position["min"], json!([0.0, 0.0, 0.0]));
+        assert_eq!(position["max"], json!([2.0, 2.0, 0.0]));
+        let index = &gltf["accessors"][primitive["indices"].as_u64().unwrap() as usize];
+        assert_eq!(index["count"], 8 * 3);
+
+        let uri = gltf["buffers"][0]["uri"].as_str().unwrap();
+        let data = uri
+            .strip_prefix("data:application/octet-stream;base64,")
+            .unwrap();
+        let bytes = STANDARD.decode(data).unwrap();
+        assert_eq!(json!(bytes.len()), gltf["buffers"][0]["byteLength"]);
+        assert!(gltf.get("images").is_none());
+
+        let Coloring::Solid(c) = material.coloring else {
+            unreachable!()
+        };
+        let base = &gltf["materials"][0]["pbrMetallicRoughness"]["baseColorFactor"];
+        assert_eq!(base[0], json!(c.r() * material.kd));
+    }
+
+    #[test]
+    fn writes_glb_chunks() {
+        let out = write(&Material::default(), true);
+        let word = |i: usize| u32::from_le_bytes(out[i..i + 4].try_into().unwrap()) as usize;
+        assert_eq!(&out[..4], b"glTF");
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8), out.len());
+
+        let json_len = word(12);
+        assert_eq!(&out[16..20], b"JSON");
+        let gltf: Value = serde_json::from_slice(&out[20..20 + json_len]).unwrap();
+        let bin = 20 + json_len;
+        assert_eq!(&out[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(word(bin), out.len() - bin - 8);
+        assert!(gltf["buffers"][0]["byteLength"].as_u64().unwrap() as usize <= word(bin));
+        assert!(gltf["buffers"][0].get("uri").is_none());
+    }
+
+    #[test]
+    fn embeds_textures_as_png() {
+        let img = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]));
+        let material = Material {
+            coloring: Coloring::Texture(Texture::from_img(img.clone(), true)),
+            normal_map: Some(Texture::from_img(img, false)),
+            ..Material::default()
+        };
+        let gltf: Value = serde_json::from_slice(&write(&material, false)).unwrap();
+        assert_eq!(gltf["images"].as_array().unwrap().len(), 2);
+        assert_eq!(gltf["images"][0]["mimeType"], "image/png");
+        let material = &gltf["materials"][0];
+        assert_eq!(
+            material["pbrMetallicRoughness"]["baseColorTexture"]["index"],
+            0
+        );
+        assert_eq!(material["normalTexture"]["index"], 1);
+        assert_eq!(gltf["samplers"][0]["magFilter"], LINEAR);
+    }
+}
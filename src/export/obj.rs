@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+
+use crate::mesh::Mesh;
+
+/// Writes the mesh as a Wavefront OBJ with shared, one-based indexed vertices
+pub fn write_obj(mesh: &Mesh, w: &mut impl Write) -> io::Result<()> {
+    let indexed = mesh.indexed();
+
+    writeln!(w, "# Bezier surface, {} triangles", indexed.triangles.len())?;
+    writeln!(w, "o surface")?;
+    for v in &indexed.vertices {
+        let p = v.pos();
+        writeln!(w, "v {} {} {}", p.x, p.y, p.z)?;
+    }
+    for v in &indexed.vertices {
+        let (u, v) = v.uv();
+        writeln!(w, "vt {u} {v}")?;
+    }
+    for v in &indexed.vertices {
+        let n = v.normal();
+        writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+    }
+    for t in &indexed.triangles {
+        let [a, b, c] = t.map(|i| i + 1);
+        writeln!(w, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::BezierSurface;
+
+    #[test]
+    fn writes_indexed_vertices_and_faces() {
+        let patch: BezierSurface = "1 1\n0 0 0\n2 0 0\n0 2 0\n2 2 0\n".parse().unwrap();
+        let mesh = Mesh::from_patches(&[patch], 3);
+        let mut out = Vec::new();
+        write_obj(&mesh, &mut out).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+
+        let count = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).count();
+        // 3 x 3 grid of vertices split into 2 x 2 quads of two triangles
+        assert_eq!(count("v "), 9);
+        assert_eq!(count("vt "), 9);
+        assert_eq!(count("vn "), 9);
+        assert_eq!(count("f "), 8);
+        assert!(obj.contains("\nv 1 1 0\n"));
+        assert!(obj.contains("\nvt 0.5 0.5\n"));
+
+        for face in obj.lines().filter(|l| l.starts_with("f ")) {
+            for corner in face.split_whitespace().skip(1) {
+                let idx: Vec<usize> = corner.split('/').map(|i| i.parse().unwrap()).collect();
+                assert!(idx[0] >= 1 && idx[0] <= 9);
+                assert!(idx.iter().all(|&i| i == idx[0]));
+            }
+        }
+    }
+}
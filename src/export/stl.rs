@@ -0,0 +1,217 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+};
+
+use crate::{
+    mesh::Mesh,
+    point::{Point3, Vector3},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StlFormat {
+    Binary,
+    Ascii,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StlOptions {
+    pub format: StlFormat,
+    /// if positive the open surface is turned into a closed solid of this thickness
+    /// by offsetting it against its normals
+    pub thickness: f32,
+}
+
+impl Default for StlOptions {
+    fn default() -> Self {
+        Self {
+            format: StlFormat::Binary,
+            thickness: 0.0,
+        }
+    }
+}
+
+pub fn write_stl(mesh: &Mesh, options: &StlOptions, w: &mut impl Write) -> io::Result<()> {
+    let triangles = if options.thickness > 0.0 {
+        solidify(mesh, options.thickness)
+    } else {
+        mesh.triangles()
+            .iter()
+            .map(|t| [t.p0.pos(), t.p1.pos(), t.p2.pos()])
+            .collect()
+    };
+
+    match options.format {
+        StlFormat::Binary => write_binary(&triangles, w)?,
+        StlFormat::Ascii => write_ascii(&triangles, w)?,
+    }
+    w.flush()
+}
+
+fn facet_normal([a, b, c]: &[Point3; 3]) -> Vector3 {
+    let n = (*b - *a).cross(*c - *a);
+    if n.length() > 0.0 {
+        n.normalized()
+    } else {
+        Vector3::zeros()
+    }
+}
+
+fn write_binary(triangles: &[[Point3; 3]], w: &mut impl Write) -> io::Result<()> {
+    let mut header = [0u8; 80];
+    let title = b"Bezier surface";
+    header[..title.len()].copy_from_slice(title);
+    w.write_all(&header)?;
+    w.write_all(&(triangles.len() as u32).to_le_bytes())?;
+
+    for t in triangles {
+        let n = facet_normal(t);
+        for c in [n.x, n.y, n.z] {
+            w.write_all(&c.to_le_bytes())?;
+        }
+        for p in t {
+            for c in [p.x, p.y, p.z] {
+                w.write_all(&c.to_le_bytes())?;
+            }
+        }
+        // attribute byte count
+        w.write_all(&0u16.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_ascii(triangles: &[[Point3; 3]], w: &mut impl Write) -> io::Result<()> {
+    writeln!(w, "solid surface")?;
+    for t in triangles {
+        let n = facet_normal(t);
+        writeln!(w, "  facet normal {} {} {}", n.x, n.y, n.z)?;
+        writeln!(w, "    outer loop")?;
+        for p in t {
+            writeln!(w, "      vertex {} {} {}", p.x, p.y, p.z)?;
+        }
+        writeln!(w, "    endloop")?;
+        writeln!(w, "  endfacet")?;
+    }
+    writeln!(w, "endsolid surface")
+}
+
+/// Closes the surface into a solid: the original surface on top, a copy offset
+/// by `thickness` against the averaged vertex normals with reversed winding
+/// at the bottom and walls joining the two along the boundary edges
+fn solidify(mesh: &Mesh, thickness: f32) -> Vec<[Point3; 3]> {
+    let indexed = mesh.indexed();
+
+    // merge vertices by position only so that patches sharing an edge are stitched
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut lookup = HashMap::new();
+    let remap: Vec<usize> = indexed
+        .vertices
+        .iter()
+        .map(|v| {
+            let p = v.pos();
+            let idx = *lookup
+                .entry([p.x, p.y, p.z].map(f32::to_bits))
+                .or_insert_with(|| {
+                    positions.push(p);
+                    normals.push(Vector3::zeros());
+                    positions.len() - 1
+                });
+            normals[idx] = normals[idx] + v.normal();
+            idx
+        })
+        .collect();
+
+    let top: Vec<[usize; 3]> = indexed
+        .triangles
+        .iter()
+        .map(|t| t.map(|i| remap[i]))
+        .filter(|[a, b, c]| a != b && b != c && c != a)
+        .collect();
+
+    let bottom: Vec<Point3> = positions
+        .iter()
+        .zip(&normals)
+        .map(|(&p, &n)| p + n.normalized() * -thickness)
+        .collect();
+
+    let edges: HashSet<(usize, usize)> = top
+        .iter()
+        .flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)])
+        .collect();
+
+    let mut triangles = Vec::with_capacity(top.len() * 2);
+    for &[a, b, c] in &top {
+        triangles.push([positions[a], positions[b], positions[c]]);
+        triangles.push([bottom[a], bottom[c], bottom[b]]);
+
+        // an edge is on the boundary if no triangle traverses it in the opposite direction
+        for (a, b) in [(a, b), (b, c), (c, a)] {
+            if !edges.contains(&(b, a)) {
+                triangles.push([positions[b], positions[a], bottom[a]]);
+                triangles.push([positions[b], bottom[a], bottom[b]]);
+            }
+        }
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::BezierSurface;
+
+    fn flat_mesh() -> Mesh {
+        let patch: BezierSurface = "1 1\n0 0 0\n2 0 0\n0 2 0\n2 2 0\n".parse().unwrap();
+        Mesh::from_patches(&[patch], 3)
+    }
+
+    fn write(options: StlOptions) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_stl(&flat_mesh(), &options, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn writes_binary() {
+        let out = write(StlOptions::default());
+        assert_eq!(out.len(), 80 + 4 + 8 * 50);
+        assert_eq!(u32::from_le_bytes(out[80..84].try_into().unwrap()), 8);
+        // facing +z
+        let nz = f32::from_le_bytes(out[92..96].try_into().unwrap());
+        assert_eq!(nz.abs(), 1.0);
+    }
+
+    #[test]
+    fn writes_ascii() {
+        let out = write(StlOptions {
+            format: StlFormat::Ascii,
+            thickness: 0.0,
+        });
+        let stl = String::from_utf8(out).unwrap();
+        assert!(stl.starts_with("solid surface\n"));
+        assert!(stl.ends_with("endsolid surface\n"));
+        assert_eq!(stl.matches("facet normal").count(), 8);
+        assert_eq!(stl.matches("vertex").count(), 24);
+    }
+
+    #[test]
+    fn thickened_surface_is_closed() {
+        let triangles = solidify(&flat_mesh(), 1.0);
+        // top and bottom, and two triangles per each of the 8 boundary edges
+        assert_eq!(triangles.len(), 8 + 8 + 2 * 8);
+
+        // every edge is shared by exactly two triangles, in opposite directions
+        let key = |p: Point3| [p.x, p.y, p.z].map(f32::to_bits);
+        let mut edges = HashMap::new();
+        for [a, b, c] in &triangles {
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((key(*p), key(*q))).or_insert(0) += 1;
+            }
+        }
+        for (&(p, q), &n) in &edges {
+            assert_eq!(n, 1);
+            assert_eq!(edges.get(&(q, p)), Some(&1));
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use std::{collections::VecDeque, mem::swap};
+
+use crate::{
+    color::Color,
+    light::Light,
+    material::{Coloring, ShadingModel},
+    point::Vector3,
+    scene::{PointHandle, Scene},
+    texture::{Texture, TextureFilter},
+    transform::Quaternion,
+};
+
+/// Oldest edits are forgotten past this count, they may hold whole textures
+const MAX_EDITS: usize = 100;
+
+/// Reversible change of the scene. Every variant except `MovePoints` holds the
+/// state to restore, applying an edit swaps it with the current one.
+pub enum Edit {
+    /// control points to be moved by `delta` (in model space)
+    MovePoints {
+        handles: Vec<PointHandle>,
+        delta: Vector3,
+    },
+    /// ambient, diffuse and specular fractions, exponent and shading model
+    Material {
+        ka: f32,
+        kd: f32,
+        ks: f32,
+        m: i32,
+        shading: ShadingModel,
+    },
+    Coloring(Coloring),
+    /// filter of the texture, left out if the surface is no longer textured
+    TextureFilter(TextureFilter),
+    NormalMap(Option<Texture>),
+    /// filter of the normal map, left out if there is no longer one
+    NormalMapFilter(TextureFilter),
+    /// all of the lights, as added, removed and edited together
+    Lights(Vec<Light>),
+    Ambient(Color),
+    Rotation(Quaternion),
+    /// mesh resolution
+    Resolution(usize),
+}
+
+impl Edit {
+    /// Applies the edit, leaving its inverse in `self`
+    fn apply(&mut self, scene: &mut Scene) {
+        match self {
+            Edit::MovePoints { handles, delta } => {
+                scene.move_points(handles, *delta);
+                *delta = *delta * -1.0;
+            }
+            Edit::Material {
+                ka,
+                kd,
+                ks,
+                m,
+                shading,
+            } => {
+                swap(ka, &mut scene.material.ka);
+                swap(kd, &mut scene.material.kd);
+                swap(ks, &mut scene.material.ks);
+                swap(m, &mut scene.material.m);
+                swap(shading, &mut scene.material.shading);
+            }
+            Edit::Coloring(coloring) => swap(coloring, &mut scene.material.coloring),
+            Edit::TextureFilter(filter) => {
+                if let Coloring::Texture(t) = &mut scene.material.coloring {
+                    swap(filter, &mut t.filter);
+                }
+            }
+            Edit::NormalMap(map) => swap(map, &mut scene.material.normal_map),
+            Edit::NormalMapFilter(filter) => {
+                if let Some(t) = &mut scene.material.normal_map {
+                    swap(filter, &mut t.filter);
+                }
+            }
+            Edit::Lights(lights) => swap(lights, &mut scene.lights),
+            Edit::Ambient(color) => swap(color, &mut scene.ambient),
+            Edit::Rotation(q) => {
+                let current = scene.orientation();
+                scene.set_orientation(*q);
+                *q = current;
+            }
+            Edit::Resolution(n) => {
+                let current = scene.mesh_resolution();
+                scene.set_mesh_resolution(*n);
+                *n = current;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl History {
+    /// Records an edit that has already been made to the scene
+    pub fn push(&mut self, edit: Edit) {
+        if self.undo.len() == MAX_EDITS {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(edit);
+        self.redo.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn undo(&mut self, scene: &mut Scene) {
+        if let Some(mut edit) = self.undo.pop_back() {
+            edit.apply(scene);
+            self.redo.push(edit);
+        }
+    }
+
+    pub fn redo(&mut self, scene: &mut Scene) {
+        if let Some(mut edit) = self.redo.pop() {
+            edit.apply(scene);
+            self.undo.push_back(edit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, point::Point3, surface::BezierSurface};
+
+    fn scene() -> Scene {
+        let patch: BezierSurface = "1 1\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n".parse().unwrap();
+        Scene::new(vec![patch])
+    }
+
+    /// Makes a change with `change`, recording `edit` for it, and checks that
+    /// undoing and redoing it brings back the values read by `state`
+    fn assert_reversible<T: PartialEq + std::fmt::Debug>(
+        scene: &mut Scene,
+        state: impl Fn(&Scene) -> T,
+        change: impl FnOnce(&mut Scene) -> Edit,
+    ) {
+        let before = state(scene);
+        let mut history = History::default();
+        let edit = change(scene);
+        history.push(edit);
+        let after = state(scene);
+        assert_ne!(before, after);
+
+        history.undo(scene);
+        assert_eq!(state(scene), before);
+        assert!(!history.can_undo() && history.can_redo());
+        history.redo(scene);
+        assert_eq!(state(scene), after);
+        assert!(history.can_undo() && !history.can_redo());
+    }
+
+    #[test]
+    fn undoes_point_moves_together() {
+        // two patches sharing their right and left edges
+        let patches = BezierSurface::parse_patches(
+            "1 1\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n1 1\n1 0 0\n2 0 0\n1 1 0\n2 1 0\n",
+        )
+        .unwrap();
+        let mut scene = Scene::new(patches);
+        let handles = vec![
+            PointHandle {
+                patch: 0,
+                row: 0,
+                col: 1,
+            },
+            PointHandle {
+                patch: 1,
+                row: 0,
+                col: 0,
+            },
+        ];
+        let points = |scene: &Scene| handles.iter().map(|&h| scene.point(h)).collect::<Vec<_>>();
+        let before = points(&scene);
+        assert_reversible(&mut scene, points, |scene| {
+            let delta = Vector3::new(0.0, 0.0, 2.0);
+            scene.move_points(&handles, delta);
+            Edit::MovePoints {
+                handles: handles.clone(),
+                delta: delta * -1.0,
+            }
+        });
+        let moved = before[0] + Vector3::new(0.0, 0.0, 2.0);
+        assert_eq!(points(&scene), vec![moved; 2]);
+    }
+
+    #[test]
+    fn undoes_material_changes() {
+        let mut scene = scene();
+        let material = |s: &Scene| {
+            let m = &s.material;
+            (m.ka, m.kd, m.ks, m.m, m.shading)
+        };
+        assert_reversible(&mut scene, material, |scene| {
+            let m = &mut scene.material;
+            let edit = Edit::Material {
+                ka: m.ka,
+                kd: m.kd,
+                ks: m.ks,
+                m: m.m,
+                shading: m.shading,
+            };
+            (m.ka, m.kd, m.ks, m.m) = (0.5, 0.1, 0.9, 3);
+            m.shading = ShadingModel::cook_torrance();
+            edit
+        });
+    }
+
+    #[test]
+    fn undoes_texture_changes() {
+        let mut scene = scene();
+        let img = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        let textured = |s: &Scene| matches!(s.material.coloring, Coloring::Texture(_));
+        let texture = Texture::from_img(img.clone(), true);
+        assert_reversible(&mut scene, textured, |scene| {
+            let coloring =
+                std::mem::replace(&mut scene.material.coloring, Coloring::Texture(texture));
+            Edit::Coloring(coloring)
+        });
+
+        let normal_map = Texture::from_img(img, false);
+        let mapped = |s: &Scene| s.material.normal_map.is_some();
+        assert_reversible(&mut scene, mapped, |scene| {
+            Edit::NormalMap(scene.material.normal_map.replace(normal_map))
+        });
+    }
+
+    #[test]
+    fn undoes_light_changes() {
+        let mut scene = scene();
+        let lights = |s: &Scene| s.lights.clone();
+        assert_reversible(&mut scene, lights, |scene| {
+            let before = scene.lights.clone();
+            scene.lights[0].intensity = 3.0;
+            let pos = Point3::new(0.0, 0.0, 500.0);
+            scene
+                .lights
+                .push(Light::new(pos, Color::new(1.0, 0.0, 0.0)));
+            Edit::Lights(before)
+        });
+
+        let ambient = |s: &Scene| s.ambient;
+        assert_reversible(&mut scene, ambient, |scene| {
+            Edit::Ambient(std::mem::replace(
+                &mut scene.ambient,
+                Color::new(0.2, 0.2, 0.2),
+            ))
+        });
+    }
+
+    #[test]
+    fn undoes_rotation_and_resolution() {
+        let mut scene = scene();
+        // up to rounding in the renormalisation
+        let orientation = |s: &Scene| {
+            let q = s.orientation();
+            [q.w, q.x, q.y, q.z].map(|c| (c * 1e4).round())
+        };
+        assert_reversible(&mut scene, orientation, |scene| {
+            let before = scene.orientation();
+            scene.set_rotation(0.1, 0.2, 0.3);
+            Edit::Rotation(before)
+        });
+        assert_reversible(&mut scene, Scene::mesh_resolution, |scene| {
+            let before = scene.mesh_resolution();
+            scene.set_mesh_resolution(7);
+            Edit::Resolution(before)
+        });
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut scene = scene();
+        let mut history = History::default();
+        history.push(Edit::Resolution(scene.mesh_resolution()));
+        history.undo(&mut scene);
+        assert!(history.can_redo());
+        history.push(Edit::Resolution(scene.mesh_resolution()));
+        assert!(!history.can_redo());
+        history.redo(&mut scene);
+        assert!(history.can_undo());
+    }
+
+    #[test]
+    fn forgets_the_oldest_edits() {
+        let mut scene = scene();
+        let mut history = History::default();
+        for n in 0..MAX_EDITS + 5 {
+            history.push(Edit::Ambient(Color::new(n as f32 / 1000.0, 0.0, 0.0)));
+        }
+        let mut undone = 0;
+        while history.can_undo() {
+            history.undo(&mut scene);
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_EDITS);
+        // the oldest edit left restores the color recorded by the sixth push
+        assert_eq!(scene.ambient, Color::new(0.005, 0.0, 0.0));
+    }
+
+    #[test]
+    fn undoes_texture_filter_changes() {
+        let mut scene = scene();
+        let img = image::RgbaImage::from_pixel(2, 2, image::Rgba([0, 0, 0, 255]));
+        scene.material.coloring = Coloring::Texture(Texture::from_img(img.clone(), true));
+        scene.material.normal_map = Some(Texture::from_img(img, false));
+        let filters = |scene: &Scene| {
+            let Coloring::Texture(t) = &scene.material.coloring else {
+                unreachable!()
+            };
+            (t.filter, scene.material.normal_map.as_ref().unwrap().filter)
+        };
+
+        let mut history = History::default();
+        if let Coloring::Texture(t) = &mut scene.material.coloring {
+            t.filter = TextureFilter::Anisotropic;
+        }
+        history.push(Edit::TextureFilter(TextureFilter::Bilinear));
+        scene.material.normal_map.as_mut().unwrap().filter = TextureFilter::Nearest;
+        history.push(Edit::NormalMapFilter(TextureFilter::Bilinear));
+
+        history.undo(&mut scene);
+        assert_eq!(
+            filters(&scene),
+            (TextureFilter::Anisotropic, TextureFilter::Bilinear)
+        );
+        history.undo(&mut scene);
+        assert_eq!(
+            filters(&scene),
+            (TextureFilter::Bilinear, TextureFilter::Bilinear)
+        );
+        history.redo(&mut scene);
+        history.redo(&mut scene);
+        assert_eq!(
+            filters(&scene),
+            (TextureFilter::Anisotropic, TextureFilter::Nearest)
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{point::Point3, surface::BezierSurface};
+
+/// Half of the extent imported models are scaled to, so that they fit the default view
+const FIT_RADIUS: f32 = 300.0;
+
+/// Parses the `.bpt` format: the number of patches followed by that many patches,
+/// each starting with an `n m` degree header (usually `3 3`) and its control points
+pub fn parse_bpt(s: &str) -> Result<Vec<BezierSurface>, String> {
+    let mut lines = s.lines().filter(|l| !l.trim().is_empty()).peekable();
+
+    let count = parse_count(lines.next(), "patches")?;
+
+    let mut patches = Vec::with_capacity(count);
+    for i in 0..count {
+        if lines.peek().is_none() {
+            return Err(format!("expected {count} patches, found {i}"));
+        }
+        let patch =
+            BezierSurface::read_patch(&mut lines).map_err(|e| format!("patch {}: {e}", i + 1))?;
+        patches.push(patch);
+    }
+
+    if lines.next().is_some() {
+        return Err(format!("unexpected data after {count} patches"));
+    }
+
+    fit_to_view(&mut patches);
+    Ok(patches)
+}
+
+/// Parses the indexed "teapot.patches" format: the number of patches, then a line
+/// of 16 one-based vertex indices per bicubic patch, then the number of vertices
+/// and one `x, y, z` line per vertex
+pub fn parse_indexed_patches(s: &str) -> Result<Vec<BezierSurface>, String> {
+    let mut lines = s.lines().filter(|l| !l.trim().is_empty());
+
+    let patch_count = parse_count(lines.next(), "patches")?;
+    let mut indices = Vec::with_capacity(patch_count);
+    for i in 0..patch_count {
+        let line = lines
+            .next()
+            .ok_or(format!("expected indices of patch {}", i + 1))?;
+        let idx = fields(line)
+            .map(|f| f.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("patch {}: index should be a natural number, {e}", i + 1))?;
+        if idx.len() != 16 {
+            return Err(format!(
+                "patch {}: expected 16 indices, found {}",
+                i + 1,
+                idx.len()
+            ));
+        }
+        indices.push(idx);
+    }
+
+    let vertex_count = parse_count(lines.next(), "vertices")?;
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for i in 0..vertex_count {
+        let line = lines.next().ok_or(format!("expected vertex {}", i + 1))?;
+        let p: Point3 = fields(line)
+            .collect::<Vec<_>>()
+            .join(" ")
+            .parse()
+            .map_err(|e| format!("vertex {}: {e}", i + 1))?;
+        vertices.push(p);
+    }
+
+    let mut patches = indices
+        .iter()
+        .enumerate()
+        .map(|(i, idx)| {
+            let points = idx
+                .chunks(4)
+                .map(|row| {
+                    row.iter()
+                        .map(|&k| {
+                            k.checked_sub(1)
+                                .and_then(|k| vertices.get(k).copied())
+                                .ok_or(format!("patch {}: vertex index {k} out of range", i + 1))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            BezierSurface::new(points, vec![vec![1.0; 4]; 4])
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    fit_to_view(&mut patches);
+    Ok(patches)
+}
+
+fn parse_count(line: Option<&str>, what: &str) -> Result<usize, String> {
+    line.ok_or(format!("expected number of {what}"))?
+        .trim()
+        .parse()
+        .map_err(|e| format!("number of {what} should be a natural number, {e}"))
+}
+
+/// Splits a line on commas and whitespace
+fn fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+}
+
+/// Centers the patches at the origin and scales them to fit [`FIT_RADIUS`],
+/// reference models usually come in units far smaller than a pixel
+fn fit_to_view(patches: &mut [BezierSurface]) {
+    let (min, max) = patches.iter().flat_map(|p| p.points()).fold(
+        (
+            Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        ),
+        |(min, max), p| {
+            (
+                Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        },
+    );
+
+    let extent = (max - min).x.max((max - min).y).max((max - min).z);
+    if !extent.is_finite() || extent <= 0.0 {
+        return;
+    }
+
+    let center = (min + max) * 0.5;
+    let scale = 2.0 * FIT_RADIUS / extent;
+    patches
+        .iter_mut()
+        .for_each(|patch| patch.map_points(|p| Point3::origin() + (p - center) * scale));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat bicubic patch over 0..=3 x 0..=3 in the given `.bpt` layout
+    fn flat_patch(z: f32) -> String {
+        let mut s = String::from("3 3\n");
+        for j in 0..4 {
+            for i in 0..4 {
+                s += &format!("{i} {j} {z}\n");
+            }
+        }
+        s
+    }
+
+    #[test]
+    fn parses_bpt() {
+        let s = format!("2\n{}\n{}", flat_patch(0.0), flat_patch(3.0));
+        let patches = parse_bpt(&s).unwrap();
+        assert_eq!(patches.len(), 2);
+        assert!(patches.iter().all(|p| p.degree() == (3, 3)));
+
+        // centered at the origin and scaled to FIT_RADIUS
+        assert_eq!(
+            *patches[0].points().next().unwrap(),
+            Point3::new(-300.0, -300.0, -300.0)
+        );
+        assert_eq!(
+            *patches[1].points().last().unwrap(),
+            Point3::new(300.0, 300.0, 300.0)
+        );
+    }
+
+    #[test]
+    fn checks_the_bpt_patch_count() {
+        let e = parse_bpt(&format!("2\n{}", flat_patch(0.0))).err().unwrap();
+        assert_eq!(e, "expected 2 patches, found 1");
+        let e = parse_bpt(&format!("1\n{}\n0 0 0\n", flat_patch(0.0)))
+            .err()
+            .unwrap();
+        assert_eq!(e, "unexpected data after 1 patches");
+        assert!(parse_bpt("x\n").is_err());
+    }
+
+    /// One patch over the 16 vertices of a flat 4 x 4 grid, listed in reverse
+    fn indexed_patch(indices: &str) -> String {
+        let mut s = format!("1\n{indices}\n16\n");
+        for k in (0..16).rev() {
+            s += &format!("{}, {}, 0.5\n", k % 4, k / 4);
+        }
+        s
+    }
+
+    #[test]
+    fn parses_indexed_patches() {
+        let indices = (1..=16).rev().map(|i| i.to_string()).collect::<Vec<_>>();
+        let patches = parse_indexed_patches(&indexed_patch(&indices.join(","))).unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].degree(), (3, 3));
+        // flat along z, so only x and y are scaled
+        assert_eq!(
+            *patches[0].points().next().unwrap(),
+            Point3::new(-300.0, -300.0, 0.0)
+        );
+        assert_eq!(
+            *patches[0].points().nth(6).unwrap(),
+            Point3::new(100.0, -100.0, 0.0)
+        );
+        assert_eq!(
+            *patches[0].points().last().unwrap(),
+            Point3::new(300.0, 300.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn checks_the_vertex_indices() {
+        let mut indices = vec!["1"; 16];
+        assert!(parse_indexed_patches(&indexed_patch(&indices[1..].join(","))).is_err());
+        indices[5] = "17";
+        let e = parse_indexed_patches(&indexed_patch(&indices.join(",")))
+            .err()
+            .unwrap();
+        assert_eq!(e, "patch 1: vertex index 17 out of range");
+        indices[5] = "0";
+        assert!(parse_indexed_patches(&indexed_patch(&indices.join(","))).is_err());
+    }
+}
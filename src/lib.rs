@@ -0,0 +1,42 @@
+//! Evaluation, triangulation and software rasterisation of (rational) Bezier surfaces.
+//!
+//! A [`Scene`] is built from patches parsed with [`BezierSurface::parse_patches`] or
+//! loaded with [`Scene::from_file`], [`render`] draws it into a caller-provided RGBA buffer.
+//! Patches in other formats are read with [`parse_bpt`] and [`parse_indexed_patches`],
+//! the scene is written with [`Scene::save`] and exported with [`Scene::export_obj`],
+//! [`Scene::export_stl`] and [`Scene::export_gltf`]. The rasteriser itself is internal.
+
+mod camera;
+mod canvas;
+mod color;
+mod export;
+mod history;
+mod import;
+mod light;
+mod material;
+mod mesh;
+mod point;
+mod render;
+mod scene;
+mod shadow;
+mod surface;
+mod texture;
+mod transform;
+mod triangle;
+
+pub use camera::Camera;
+pub use canvas::Canvas;
+pub use color::Color;
+pub use export::stl::{StlFormat, StlOptions};
+pub use history::{Edit, History};
+pub use import::{parse_bpt, parse_indexed_patches};
+pub use light::{Attenuation, Light, LightKind};
+pub use material::{Coloring, Material, ShadingModel};
+pub use point::{Point3, Vector3};
+pub use render::{
+    Antialiasing, Rasterizer, RenderOptions, ShadowOptions, ToneMapping, Visible, render,
+};
+pub use scene::{PointHandle, Scene};
+pub use surface::BezierSurface;
+pub use texture::{Texture, TextureFilter};
+pub use transform::Quaternion;
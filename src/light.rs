@@ -1,39 +1,187 @@
-use crate::{color::Color, point::Point3};
+use crate::{
+    color::Color,
+    point::{Point3, Vector3},
+};
 
-#[derive(Debug, Clone)]
+/// How the light spreads from its source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// shines from `pos` in all directions
+    Point,
+    /// parallel rays along `dir`, as from a very distant source
+    Directional,
+    /// shines from `pos` in a cone around `dir`, at full strength up to the `inner`
+    /// angle from its axis and fading out towards the `outer` one (in radians)
+    Spot { inner: f32, outer: f32 },
+}
+
+impl LightKind {
+    /// Spotlight with the cone used by default
+    pub fn spot() -> Self {
+        Self::Spot {
+            inner: 15.0f32.to_radians(),
+            outer: 30.0f32.to_radians(),
+        }
+    }
+}
+
+/// Falloff of the light with the distance `d` from its source,
+/// by a factor of 1 / (constant + linear * d + quadratic * d^2)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub fn factor(&self, d: f32) -> f32 {
+        1.0 / (self.constant + self.linear * d + self.quadratic * d * d).max(1e-6)
+    }
+
+    /// Smallest constant term keeping the light from becoming infinitely bright
+    /// close to its source, positive when there is no linear or quadratic term
+    pub fn min_constant(&self) -> f32 {
+        if self.linear == 0.0 && self.quadratic == 0.0 {
+            0.01
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Default for Attenuation {
+    /// No falloff
+    fn default() -> Self {
+        Self {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.0,
+        }
+    }
+}
+
+/// Lights of the scene together with the ambient light reaching every point
+pub struct Lighting<'a> {
+    pub ambient: Color,
+    pub lights: &'a [Light],
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Light {
+    pub kind: LightKind,
     pub pos: Point3,
+    /// direction the light shines in, for directional lights and spotlights
+    pub dir: Vector3,
     pub color: Color,
+    /// multiplier of the color
+    pub intensity: f32,
+    /// falloff with the distance, ignored by directional lights
+    pub attenuation: Attenuation,
     /// advancement in animation
     pub t: f32,
     pub is_animating: bool,
-    /// r = 0 for point light, otherwise I = I0 * cos^r(theta)
-    pub r: i32,
 }
 
 impl Light {
+    /// Point light at `pos`, shining towards the origin if turned into a spotlight
     pub fn new(pos: Point3, color: Color) -> Self {
         Self {
+            kind: LightKind::Point,
             pos,
+            dir: (Point3::origin() - pos).normalized(),
             color,
+            intensity: 1.0,
+            attenuation: Attenuation::default(),
             t: 0.0,
             is_animating: true,
-            r: 0,
         }
     }
 
-    pub fn advance_animation(&mut self, dt: f32) {
-        if self.is_animating {
-            self.t += dt * 1e-1;
-            if self.t > 1.0 {
-                self.t = 0.0;
+    /// Unit vector from `p` towards the light, together with the fraction
+    /// of the light reaching `p` given its distance and the cone of a spotlight
+    pub fn incidence(&self, p: Point3) -> (Vector3, f32) {
+        let to_light = self.pos - p;
+        let falloff = self.attenuation.factor(to_light.length());
+        let to_light = to_light.normalized();
+        match self.kind {
+            LightKind::Point => (to_light, falloff),
+            LightKind::Directional => (self.dir.normalized() * -1.0, 1.0),
+            LightKind::Spot { inner, outer } => {
+                let cos = -to_light.dot(self.dir.normalized());
+                let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+                let f = ((cos - cos_outer) / (cos_inner - cos_outer).max(1e-6)).clamp(0.0, 1.0);
+                // smoothstep
+                (to_light, falloff * f * f * (3.0 - 2.0 * f))
             }
         }
+    }
+
+    /// Moves the light along its orbit while it is animating,
+    /// otherwise it stays where it was placed
+    pub fn advance_animation(&mut self, dt: f32) {
+        if !self.is_animating {
+            return;
+        }
+        self.t += dt * 1e-1;
+        if self.t > 1.0 {
+            self.t = 0.0;
+        }
+        self.place_on_orbit(self.t);
+    }
 
+    /// Places the light at `t` in 0..=1 along its orbit around the OZ axis,
+    /// keeping its height
+    pub fn place_on_orbit(&mut self, t: f32) {
+        self.t = t;
         let base_radius = 300.0;
-        let r = base_radius + 40.0 * (self.t * std::f32::consts::TAU * 3.0).sin(); // 3 inner loops
-        let angle = self.t * std::f32::consts::TAU;
+        let r = base_radius + 40.0 * (t * std::f32::consts::TAU * 3.0).sin(); // 3 inner loops
+        let angle = t * std::f32::consts::TAU;
         self.pos.x = r * angle.cos();
         self.pos.y = r * angle.sin();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_in_place_unless_animating() {
+        let pos = Point3::new(-600.0, 700.0, 300.0);
+        let mut light = Light::new(pos, Color::new(1.0, 1.0, 1.0));
+        light.is_animating = false;
+        light.advance_animation(0.0);
+        light.advance_animation(0.5);
+        assert_eq!(light.pos, pos);
+
+        light.is_animating = true;
+        light.advance_animation(0.5);
+        assert_eq!(light.t, 0.05);
+        assert_eq!(light.pos.z, pos.z);
+        assert!((light.pos.x.hypot(light.pos.y) - 300.0).abs() < 41.0);
+    }
+
+    #[test]
+    fn attenuation_falls_off_with_distance() {
+        let a = Attenuation {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 0.01,
+        };
+        assert_eq!(a.factor(0.0), 1.0);
+        assert_eq!(a.factor(10.0), 0.5);
+        assert_eq!(Attenuation::default().factor(1e4), 1.0);
+    }
+
+    #[test]
+    fn constant_term_stays_positive_alone() {
+        assert!(Attenuation::default().min_constant() > 0.0);
+        let a = Attenuation {
+            constant: 0.0,
+            linear: 0.01,
+            quadratic: 0.0,
+        };
+        assert_eq!(a.min_constant(), 0.0);
+    }
+}
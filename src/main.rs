@@ -1,21 +1,40 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use crate::app::PolygonApp;
+use std::process::exit;
+
+use bezier_surface::Scene;
+
+use crate::{app::PolygonApp, cli::Args};
 
 mod app;
-mod canvas;
-mod light;
-mod material;
-mod mesh;
-mod point;
-mod scene;
-mod color;
-mod texture;
-mod surface;
-mod triangle;
+mod cli;
 
 fn main() -> eframe::Result {
-    let app = PolygonApp::new();
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1)
+    });
+    if args.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    let mut scene = Scene::from_file(args.points.clone())
+        .and_then(|mut scene| args.apply(&mut scene).map(|_| scene))
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1)
+        });
+
+    if let Some(out) = &args.render {
+        if let Err(e) = cli::render(&mut scene, &args, out) {
+            eprintln!("{e}");
+            exit(1)
+        }
+        return Ok(());
+    }
+
+    let app = PolygonApp::new(scene, args.normals);
 
     let mut native_options = eframe::NativeOptions::default();
     native_options.viewport = native_options.viewport.with_maximized(true);
@@ -1,4 +1,10 @@
-use crate::{color::Color, point::Vector3, texture::Texture};
+use std::f32::consts::PI;
+
+use crate::{
+    color::Color,
+    point::Vector3,
+    texture::{Texture, UvDerivatives},
+};
 
 #[derive(Debug)]
 pub enum Coloring {
@@ -6,39 +12,131 @@ pub enum Coloring {
     Texture(Texture),
 }
 
+/// How the light is reflected by the surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShadingModel {
+    /// diffuse and specular fractions `kd` and `ks` of the material, with the highlight
+    /// given by the angle between the view and the reflected light raised to `m`
+    Phong,
+    /// as [`ShadingModel::Phong`], with the angle between the normal and the vector
+    /// halfway between the view and the light in place of the reflection
+    BlinnPhong,
+    /// Cook-Torrance BRDF with GGX distribution, Smith geometry and Schlick Fresnel,
+    /// between a dielectric and a metal (`metallic` in 0..=1), from a mirror-like
+    /// to a fully matte surface (`roughness` in 0..=1)
+    CookTorrance { metallic: f32, roughness: f32 },
+}
+
+impl ShadingModel {
+    /// Cook-Torrance model of a slightly rough dielectric
+    pub fn cook_torrance() -> Self {
+        Self::CookTorrance {
+            metallic: 0.0,
+            roughness: 0.5,
+        }
+    }
+}
+
 pub struct Material {
     /// base surface color
     pub coloring: Coloring,
     /// map altering normal vectors (if `None` the normals remain unchanged)
     pub normal_map: Option<Texture>,
+    /// ambient fraction
+    pub ka: f32,
     /// diffuse fraction
     pub kd: f32,
     /// specular fraction
     pub ks: f32,
     /// specular exponent
     pub m: i32,
+    pub shading: ShadingModel,
 }
 
 impl Material {
-    pub fn color_at(&self, u: f32, v: f32) -> Color {
+    /// Linear base color, textures are filtered over the footprint
+    /// of a pixel given by `d`
+    pub fn color_at(&self, u: f32, v: f32, d: &UvDerivatives) -> Color {
         match &self.coloring {
             Coloring::Solid(c) => *c,
             Coloring::Texture(t) => {
-                let c = t.sample(u, v);
-                Color::from_slice([
-                    c[0] as f32 / 255.0,
-                    c[1] as f32 / 255.0,
-                    c[2] as f32 / 255.0,
-                ])
+                let c = t.sample(u, v, d);
+                // the filter weights may add up to slightly more than 1
+                Color::new(c[0].min(1.0), c[1].min(1.0), c[2].min(1.0))
             }
         }
     }
 
-    pub fn normal_at(&self, u: f32, v: f32, pu: Vector3, pv: Vector3, n: Vector3) -> Vector3 {
+    /// Light of each channel reflected towards `to_eye` at a point of color `color`
+    /// and normal `n`, lit with a unit of light coming from `to_light` (all of the vectors
+    /// are unit). A white surface lit head-on reflects at most all of the light.
+    pub fn reflectance(
+        &self,
+        color: Color,
+        n: Vector3,
+        to_light: Vector3,
+        to_eye: Vector3,
+    ) -> [f32; 3] {
+        let nl = n.dot(to_light);
+        if nl <= 0.0 {
+            return [0.0; 3];
+        }
+
+        let phong = |spec: f32| {
+            color
+                .as_slice()
+                .map(|c| c * (self.kd * nl + self.ks * spec))
+        };
+        match self.shading {
+            ShadingModel::Phong => {
+                let r = n * (2.0 * nl) - to_light;
+                phong(to_eye.dot(r).max(0.0).powi(self.m))
+            }
+            ShadingModel::BlinnPhong => {
+                let h = (to_light + to_eye).normalized();
+                phong(n.dot(h).max(0.0).powi(self.m))
+            }
+            ShadingModel::CookTorrance {
+                metallic,
+                roughness,
+            } => {
+                // too sharp highlights would fall between the pixels
+                let roughness = roughness.clamp(0.05, 1.0);
+                let nv = n.dot(to_eye).max(1e-4);
+                let h = (to_light + to_eye).normalized();
+                let nh = n.dot(h).max(0.0);
+                let vh = to_eye.dot(h).max(0.0);
+
+                let d = ggx(nh, roughness);
+                let k = (roughness + 1.0).powi(2) / 8.0;
+                let g = nl / (nl * (1.0 - k) + k) * nv / (nv * (1.0 - k) + k);
+                let spec = d * g / (4.0 * nl * nv);
+
+                // the light is scaled by pi, so that a white Lambertian surface lit head-on
+                // reflects all of it, as in the Phong models with kd = 1
+                color.as_slice().map(|c| {
+                    let f0 = 0.04 + (c - 0.04) * metallic;
+                    let f = schlick(f0, vh);
+                    let diffuse = (1.0 - f) * (1.0 - metallic) * c / PI;
+                    (diffuse + f * spec) * nl * PI
+                })
+            }
+        }
+    }
+
+    pub fn normal_at(
+        &self,
+        u: f32,
+        v: f32,
+        d: &UvDerivatives,
+        pu: Vector3,
+        pv: Vector3,
+        n: Vector3,
+    ) -> Vector3 {
         match &self.normal_map {
             None => n,
             Some(t) => {
-                let m = t.sample_normal(u, v);
+                let m = t.sample_normal(u, v, d);
                 Vector3 {
                     x: m.x * pu.x + m.y * pv.x + m.z * n.x,
                     y: m.x * pu.y + m.y * pv.y + m.z * n.y,
@@ -49,15 +147,96 @@ impl Material {
     }
 }
 
+/// GGX normal distribution at the cosine `nh` between the normal and the half vector,
+/// with alpha = roughness²
+fn ggx(nh: f32, roughness: f32) -> f32 {
+    let a2 = roughness.powi(4);
+    a2 / (PI * (nh * nh * (a2 - 1.0) + 1.0).powi(2))
+}
+
+/// Schlick approximation of the Fresnel reflectance with reflectance `f0` at normal
+/// incidence, at the cosine `vh` between the view and the half vector
+fn schlick(f0: f32, vh: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - vh).powi(5)
+}
+
 impl Default for Material {
     fn default() -> Self {
         let solid = Coloring::Solid(Color::new(0.0, 1.0, 0.0));
         Self {
             coloring: solid,
             normal_map: None,
+            ka: 0.1,
             kd: 0.5,
             ks: 0.5,
             m: 4,
+            shading: ShadingModel::Phong,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metal(roughness: f32) -> Material {
+        Material {
+            shading: ShadingModel::CookTorrance {
+                metallic: 1.0,
+                roughness,
+            },
+            ..Material::default()
+        }
+    }
+
+    #[test]
+    fn ggx_distribution_is_normalized() {
+        // projected onto the surface, the microfacets cover it exactly once:
+        // the integral of D(h) (n.h) over the hemisphere, 2 pi D(cos) cos dcos
+        for roughness in [0.1, 0.3, 0.6, 1.0] {
+            let steps = 200_000;
+            let sum: f64 = (0..steps)
+                .map(|i| {
+                    let nh = (i as f32 + 0.5) / steps as f32;
+                    (ggx(nh, roughness) * nh) as f64
+                })
+                .sum();
+            let integral = 2.0 * std::f64::consts::PI * sum / steps as f64;
+            assert!((integral - 1.0).abs() < 1e-2, "{roughness}: {integral}");
+        }
+    }
+
+    #[test]
+    fn fresnel_at_normal_incidence_is_f0() {
+        for f0 in [0.04, 0.5, 0.95] {
+            assert_eq!(schlick(f0, 1.0), f0);
+            assert_eq!(schlick(f0, 0.0), 1.0);
+        }
+
+        // a metal seen and lit head-on reflects its own color
+        let color = Color::new(0.2, 0.5, 1.0);
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let r = metal(0.5).reflectance(color, n, n, n);
+        let scale = r[2] / color.b();
+        for (r, c) in r.iter().zip(color.as_slice()) {
+            assert!((r / c - scale).abs() < 1e-5, "{r} / {c}");
+        }
+    }
+
+    #[test]
+    fn no_light_from_behind_the_surface() {
+        let color = Color::new(1.0, 1.0, 1.0);
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let behind = Vector3::new(0.6, 0.0, -0.8);
+        let eye = Vector3::new(-0.6, 0.0, 0.8);
+        for material in [metal(0.2), metal(1.0)] {
+            assert_eq!(material.reflectance(color, n, behind, eye), [0.0; 3]);
+            assert!(material.reflectance(color, n, eye, n)[0] > 0.0);
         }
+        let dielectric = Material {
+            shading: ShadingModel::cook_torrance(),
+            ..Material::default()
+        };
+        assert_eq!(dielectric.reflectance(color, n, behind, eye), [0.0; 3]);
     }
 }
@@ -1,6 +1,22 @@
-use eframe::egui::Painter;
+use std::{collections::HashMap, sync::Mutex, thread};
 
-use crate::{canvas::Canvas, light::Light, material::Material, triangle::Triangle};
+use eframe::egui::{Painter, Pos2};
+
+use crate::{
+    camera::Camera,
+    canvas::{Canvas, Tile},
+    light::Lighting,
+    material::Material,
+    point::{Point3, Vector3},
+    render::RenderOptions,
+    shadow::ShadowMap,
+    surface::BezierSurface,
+    transform::Matrix4,
+    triangle::{Triangle, Vertex, draw_normal},
+};
+
+/// Height in rows of the tiles rendered in parallel
+const TILE_ROWS: usize = 16;
 
 pub struct Mesh {
     triangles: Vec<Triangle>,
@@ -15,41 +31,295 @@ impl Mesh {
         }
     }
 
+    /// Triangulates every patch with the given resolution into a single mesh,
+    /// welding the vertices on the edges shared by neighbouring patches
+    pub fn from_patches(patches: &[BezierSurface], resolution: usize) -> Self {
+        let mut triangles: Vec<Triangle> = patches
+            .iter()
+            .flat_map(|p| p.triangulate(resolution))
+            .collect();
+        weld_seams(&mut triangles);
+        Self::new(triangles, resolution)
+    }
+
     pub fn resolution(&self) -> usize {
         self.resolution
     }
 
-    pub fn rotate_ox(&mut self, delta: f32) {
-        self.triangles.iter_mut().for_each(|t| {
-            t.p0.rotate_ox(delta);
-            t.p1.rotate_ox(delta);
-            t.p2.rotate_ox(delta);
-        });
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
     }
 
-    pub fn rotate_oz(&mut self, delta: f32) {
-        self.triangles.iter_mut().for_each(|t| {
-            t.p0.rotate_oz(delta);
-            t.p1.rotate_oz(delta);
-            t.p2.rotate_oz(delta);
-        });
+    /// Merges identical vertices of neighbouring triangles
+    pub fn indexed(&self) -> IndexedMesh {
+        let mut vertices = Vec::new();
+        let mut lookup = HashMap::new();
+        let mut index_of = |v: Vertex| {
+            let (u, t) = v.uv();
+            let (p, n) = (v.pos(), v.normal());
+            let key = [p.x, p.y, p.z, n.x, n.y, n.z, u, t].map(f32::to_bits);
+            *lookup.entry(key).or_insert_with(|| {
+                vertices.push(v);
+                vertices.len() - 1
+            })
+        };
+
+        let triangles = self
+            .triangles
+            .iter()
+            .map(|t| [index_of(t.p0), index_of(t.p1), index_of(t.p2)])
+            .collect();
+
+        IndexedMesh {
+            vertices,
+            triangles,
+        }
     }
 
-    pub fn draw_outlines(&self, painter: &Painter) {
+    pub fn draw_outlines(&self, painter: &Painter, project: impl Fn(Point3) -> Option<Pos2>) {
         self.triangles
             .iter()
-            .for_each(|t| t.draw_outline(painter));
+            .for_each(|t| t.draw_outline(painter, &project));
+    }
+
+    /// Mesh moved by `m`, see [`Triangle::transformed`]
+    pub fn transformed(&self, m: &Matrix4) -> Self {
+        let triangles = self.triangles.iter().map(|t| t.transformed(m)).collect();
+        Self::new(triangles, self.resolution)
     }
 
+    /// Rasterises the mesh, in world space, lit by `lighting` and shadowed as given by
+    /// the shadow maps of its lights. The canvas is split into tiles, each triangle
+    /// is binned into the tiles it overlaps and the tiles are filled in parallel by
+    /// `options.threads` threads. The samples are then resolved and tone mapped
+    /// into the canvas image.
     pub fn draw_fillings(
         &self,
         canvas: &mut Canvas,
-        light: &Light,
+        camera: &Camera,
+        lighting: &Lighting,
+        shadows: &[ShadowMap],
         material: &Material,
-        draw_normals: bool,
+        options: &RenderOptions,
     ) {
-        self.triangles
+        canvas.set_antialiasing(options.antialiasing);
+        let (w, h) = canvas.raster_size();
+        if w == 0 || h == 0 {
+            // nothing to bin the triangles into
+            return;
+        }
+        let (w, h) = (w as f32, h as f32);
+
+        // the parts of the triangles behind the camera are cut off
+        let mut clipped = Vec::with_capacity(self.triangles.len());
+        for t in &self.triangles {
+            t.clip_near(camera, &mut clipped);
+        }
+
+        let mut tiles: Vec<(Tile, Vec<&Triangle>)> = canvas
+            .tiles(TILE_ROWS)
+            .into_iter()
+            .map(|tile| (tile, Vec::new()))
+            .collect();
+        for t in &clipped {
+            let Some((top, bottom)) = t.screen_rows(camera, w, h) else {
+                continue;
+            };
+            if !(bottom >= 0.0 && top < h) {
+                continue;
+            }
+            let first = top.floor().max(0.0) as usize / TILE_ROWS;
+            let last = bottom.ceil().min(h - 1.0) as usize / TILE_ROWS;
+            for (_, bin) in &mut tiles[first..=last] {
+                bin.push(t);
+            }
+        }
+
+        let queue = Mutex::new(tiles.into_iter());
+        let normals = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for _ in 0..options.threads.max(1) {
+                s.spawn(|| {
+                    loop {
+                        let Some((mut tile, bin)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        for t in bin {
+                            t.draw_filling(&mut tile, camera, lighting, shadows, material, options);
+                        }
+                        normals.lock().unwrap().append(&mut tile.normals);
+                    }
+                });
+            }
+        });
+        drop(queue);
+
+        for (p, n) in normals.into_inner().unwrap() {
+            draw_normal(canvas, camera, p, n);
+        }
+        canvas.resolve(options.tone_mapping, options.exposure);
+    }
+}
+
+/// Largest angle between the normals averaged across a seam, sharper creases
+/// keep the normals of their faces
+const SMOOTH_ANGLE: f32 = 40.0 * std::f32::consts::PI / 180.0;
+
+/// Moves the vertices lying at the same point (up to rounding), such as those
+/// evaluated separately on both sides of a seam between patches, onto a single
+/// position so that the seam is closed. Each vertex gets the average of the
+/// normals at that point within [`SMOOTH_ANGLE`] of its own, so smooth seams are
+/// shaded smoothly while creases stay sharp
+fn weld_seams(triangles: &mut [Triangle]) {
+    let Some(size) = triangles
+        .iter()
+        .flat_map(|t| [t.p0.pos(), t.p1.pos(), t.p2.pos()])
+        .map(|p| p.x.abs().max(p.y.abs()).max(p.z.abs()))
+        .reduce(f32::max)
+    else {
+        return;
+    };
+    // largest distance between the vertices merged together
+    let tolerance = (size * 1e-5).max(f32::MIN_POSITIVE);
+    let cell = |p: Point3| [p.x, p.y, p.z].map(|c| (c / tolerance).floor() as i64);
+
+    // welded points with the distinct normals of their vertices, found through
+    // the grid cell of their first vertex
+    let mut points: Vec<(Point3, Vec<Vector3>)> = Vec::new();
+    let mut grid: HashMap<[i64; 3], usize> = HashMap::new();
+    let mut point_of = |v: &Vertex| {
+        let [x, y, z] = cell(v.pos());
+        let neighbours = (-1..=1).flat_map(|dx| {
+            (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| [x + dx, y + dy, z + dz]))
+        });
+        // most vertices are repeated in several triangles, their own cell is tried first
+        let found = std::iter::once([x, y, z])
+            .chain(neighbours)
+            .filter_map(|c| grid.get(&c).copied())
+            .find(|&i| (points[i].0 - v.pos()).length() <= tolerance);
+        let i = found.unwrap_or_else(|| {
+            points.push((v.pos(), Vec::new()));
+            grid.insert([x, y, z], points.len() - 1);
+            points.len() - 1
+        });
+        if !points[i].1.contains(&v.normal()) {
+            points[i].1.push(v.normal());
+        }
+        i
+    };
+    let indices: Vec<usize> = triangles
+        .iter()
+        .flat_map(|t| [t.p0, t.p1, t.p2])
+        .map(|v| point_of(&v))
+        .collect();
+
+    for (t, indices) in triangles.iter_mut().zip(indices.chunks_exact(3)) {
+        for (v, &i) in [&mut t.p0, &mut t.p1, &mut t.p2].into_iter().zip(indices) {
+            let (pos, normals) = &points[i];
+            if normals.len() < 2 {
+                continue;
+            }
+            let own = v.normal().normalized();
+            let sum = normals
+                .iter()
+                .filter(|n| n.length() > 1e-6)
+                .map(|n| n.normalized())
+                .filter(|n| n.dot(own) >= SMOOTH_ANGLE.cos() - 1e-6)
+                .fold(Vector3::zeros(), |sum, n| sum + n);
+            // a degenerate normal is left as it is
+            let normal = if sum.length() > 1e-6 {
+                sum.normalized()
+            } else {
+                v.normal()
+            };
+            let (u, t) = v.uv();
+            *v = Vertex::new(*pos, normal, v.pu(), v.pv(), u, t);
+        }
+    }
+}
+
+/// Mesh with vertices shared between triangles, as used by the exporters
+pub struct IndexedMesh {
+    pub vertices: Vec<Vertex>,
+    /// counter-clockwise (seen from the normal side) vertex indices
+    pub triangles: Vec<[usize; 3]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two flat patches meeting at a right angle along x = 100
+    const CREASE: &str = "1 1\n\
+        -100 -100 0\n100 -100 0\n-100 100 0\n100 100 0\n\
+        1 1\n\
+        100 -100 0\n100 -100 -200\n100 100 0\n100 100 -200\n";
+
+    /// Two flat patches meeting at 20° along x = 100
+    const SMOOTH: &str = "1 1\n\
+        -100 -100 0\n100 -100 0\n-100 100 0\n100 100 0\n\
+        1 1\n\
+        100 -100 0\n300 -100 -72.794\n100 100 0\n300 100 -72.794\n";
+
+    /// Vertices of the mesh on the seam x = 100, z = 0
+    fn seam(mesh: &Mesh) -> Vec<Vertex> {
+        mesh.triangles
+            .iter()
+            .flat_map(|t| [t.p0, t.p1, t.p2])
+            .filter(|v| (v.pos().x - 100.0).abs() < 1e-3 && v.pos().z.abs() < 1e-3)
+            .collect()
+    }
+
+    #[test]
+    fn welds_positions_and_keeps_crease_normals() {
+        let patches = BezierSurface::parse_patches(CREASE).unwrap();
+        let seam = seam(&Mesh::from_patches(&patches, 4));
+        assert!(!seam.is_empty());
+
+        let faces = [Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 0.0, 0.0)];
+        for v in &seam {
+            let n = v.normal().normalized();
+            assert!(
+                faces.iter().any(|f| (n.dot(*f) - 1.0).abs() < 1e-5),
+                "{n:?}"
+            );
+            for w in seam.iter().filter(|w| (w.pos().y - v.pos().y).abs() < 1e-3) {
+                assert_eq!(v.pos(), w.pos());
+            }
+        }
+        // both sides of the crease keep their own normals
+        for f in faces {
+            assert!(
+                seam.iter()
+                    .any(|v| (v.normal().normalized().dot(f) - 1.0).abs() < 1e-5)
+            );
+        }
+    }
+
+    #[test]
+    fn averages_normals_across_smooth_seams() {
+        let patches = BezierSurface::parse_patches(SMOOTH).unwrap();
+        let seam = seam(&Mesh::from_patches(&patches, 4));
+        assert!(!seam.is_empty());
+
+        let half = 10.0f32.to_radians();
+        let expected = Vector3::new(half.sin(), 0.0, half.cos());
+        for v in &seam {
+            let n = v.normal().normalized();
+            assert!((n.dot(expected) - 1.0).abs() < 1e-5, "{n:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_normals_inside_patches() {
+        let patches = BezierSurface::parse_patches(CREASE).unwrap();
+        let mesh = Mesh::from_patches(&patches, 4);
+        let inner = mesh
+            .triangles
             .iter()
-            .for_each(|t| t.draw_filling(canvas, light, material, draw_normals));
+            .flat_map(|t| [t.p0, t.p1, t.p2])
+            .find(|v| v.pos().x < 50.0)
+            .unwrap();
+        assert!((inner.normal().z.abs() - 1.0).abs() < 1e-6);
     }
 }
@@ -3,9 +3,7 @@ use std::{
     str::FromStr,
 };
 
-use eframe::egui::{self, Pos2, pos2};
-
-use crate::canvas::Canvas;
+use eframe::egui::{Pos2, pos2};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point3 {
@@ -27,40 +25,9 @@ impl Point3 {
         }
     }
 
-    pub fn to_screen(self, canvas: &Canvas) -> Self {
-        Self {
-            x: self.x + (canvas.width() as f32) * 0.5,
-            y: -self.y + (canvas.height() as f32) * 0.5,
-            z: self.z,
-        }
-    }
-
-    pub fn to_viewport_center(self, ctx: &egui::Context) -> Self {
-        let sz = ctx.used_size();
-        Self {
-            x: self.x + sz.x * 0.5,
-            y: -self.y + sz.y * 0.5,
-            z: self.z,
-        }
-    }
-
     pub fn projection(&self) -> Pos2 {
         pos2(self.x, self.y)
     }
-
-    pub fn rotate_ox(&mut self, rot: f32) {
-        let (s, c) = rot.sin_cos();
-        let (y, z) = (self.y, self.z);
-        self.y = y * c - z * s;
-        self.z = y * s + z * c;
-    }
-
-    pub fn rotate_oz(&mut self, rot: f32) {
-        let (s, c) = rot.sin_cos();
-        let (x, y) = (self.x, self.y);
-        self.x = x * c - y * s;
-        self.y = x * s + y * c;
-    }
 }
 
 impl FromStr for Point3 {
@@ -112,8 +79,12 @@ impl Vector3 {
         }
     }
 
+    pub fn length(&self) -> f32 {
+        (self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt()
+    }
+
     pub fn normalized(&self) -> Self {
-        let l = (self.x.powi(2) + self.y.powi(2) + self.z.powi(2)).sqrt();
+        let l = self.length();
         Self {
             x: self.x / l,
             y: self.y / l,
@@ -121,18 +92,6 @@ impl Vector3 {
         }
     }
 
-    pub fn rotate_ox(&mut self, rot: f32) {
-        let mut p = Point3::new(self.x, self.y, self.z);
-        p.rotate_ox(rot);
-        Self::new(p.x, p.y, p.z);
-    }
-
-    pub fn rotate_oz(&mut self, rot: f32) {
-        let mut p = Point3::new(self.x, self.y, self.z);
-        p.rotate_oz(rot);
-        Self::new(p.x, p.y, p.z);
-    }
-
     pub fn dot(&self, rhs: Self) -> f32 {
         self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
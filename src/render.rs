@@ -0,0 +1,221 @@
+use std::{num::NonZeroUsize, thread};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{canvas::Canvas, scene::Scene};
+
+/// Layers drawn in the interactive viewport
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Visible {
+    pub polygon: bool,
+    pub mesh: bool,
+    pub filling: bool,
+    pub light_pos: bool,
+    pub normals: bool,
+}
+
+impl Default for Visible {
+    fn default() -> Self {
+        Self {
+            polygon: false,
+            mesh: false,
+            filling: true,
+            light_pos: true,
+            normals: false,
+        }
+    }
+}
+
+/// Algorithm filling the triangles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rasterizer {
+    /// scanlines crossing a sorted table of active edges
+    #[default]
+    Scanline,
+    /// half-space test of the edge functions, watertight with a top-left fill rule
+    EdgeFunction,
+}
+
+/// Smoothing of the edges of the surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Antialiasing {
+    #[default]
+    Off,
+    /// renders `k` x `k` pixels for every pixel of the image and averages them
+    Supersampling(usize),
+    /// shades every pixel once but tests coverage and depth at `n` samples
+    /// (2, 4 or 8), always filling the triangles with edge functions
+    Multisampling(usize),
+}
+
+/// Operator compressing the linear HDR colors of the shaded surface into 0..=1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+    /// 1 - e^-x, as the response of a film to the exposure
+    Exposure,
+    /// x / (1 + x)
+    Reinhard,
+    /// fit of the ACES filmic curve by Krzysztof Narkowicz
+    #[default]
+    AcesFilmic,
+}
+
+impl ToneMapping {
+    /// Maps a linear color component `x` into 0..=1
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.max(0.0);
+        match self {
+            ToneMapping::Exposure => 1.0 - (-x).exp(),
+            ToneMapping::Reinhard => x / (1.0 + x),
+            ToneMapping::AcesFilmic => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (x * (a * x + b) / (x * (c * x + d) + e)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Shadows cast by the surface on itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowOptions {
+    pub enabled: bool,
+    /// width and height in texels of each face of the shadow map
+    pub resolution: usize,
+    /// depth offset in world units keeping surfaces from shadowing themselves
+    pub bias: f32,
+}
+
+impl Default for ShadowOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resolution: 1024,
+            bias: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// draw normal vectors over the filling
+    pub draw_normals: bool,
+    /// interpolate vertex attributes with 1 / w weighting instead of
+    /// linearly in screen space (affine)
+    pub perspective_correct: bool,
+    /// number of threads filling the canvas tiles
+    pub threads: usize,
+    pub rasterizer: Rasterizer,
+    pub antialiasing: Antialiasing,
+    pub shadows: ShadowOptions,
+    pub tone_mapping: ToneMapping,
+    /// scale of the light before tone mapping, in stops
+    pub exposure: f32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            draw_normals: false,
+            perspective_correct: true,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            rasterizer: Rasterizer::default(),
+            antialiasing: Antialiasing::default(),
+            shadows: ShadowOptions::default(),
+            tone_mapping: ToneMapping::default(),
+            exposure: 0.0,
+        }
+    }
+}
+
+/// Rasterises the scene into `buffer` holding `width` x `height` sRGB RGBA pixels row by row,
+/// pixels not covered by the surface are left fully transparent
+///
+/// # Panics
+///
+/// If the buffer length is not `width * height * 4`
+pub fn render(
+    scene: &Scene,
+    options: &RenderOptions,
+    buffer: &mut [u8],
+    width: usize,
+    height: usize,
+) {
+    assert_eq!(
+        buffer.len(),
+        width * height * 4,
+        "buffer should hold width * height RGBA pixels"
+    );
+
+    let mut canvas = Canvas::new(width, height);
+    scene.draw_fillings(&mut canvas, options);
+    buffer.copy_from_slice(canvas.buffer());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 3] = [
+        ToneMapping::Exposure,
+        ToneMapping::Reinhard,
+        ToneMapping::AcesFilmic,
+    ];
+
+    #[test]
+    fn tone_mapping_is_monotonic_in_unit_range() {
+        for op in OPERATORS {
+            assert_eq!(op.apply(0.0), 0.0);
+            assert_eq!(op.apply(-1.0), 0.0);
+            let mut prev = 0.0;
+            for i in 1..=1000 {
+                let y = op.apply(i as f32 * 0.05);
+                assert!((0.0..=1.0).contains(&y), "{op:?}: {y}");
+                assert!(y >= prev, "{op:?} decreasing at {i}");
+                prev = y;
+            }
+            assert!(op.apply(1e4) > 0.99, "{op:?}");
+        }
+    }
+
+    #[test]
+    fn tone_mapping_values() {
+        assert!((ToneMapping::Exposure.apply(2f32.ln()) - 0.5).abs() < 1e-6);
+        assert_eq!(ToneMapping::Reinhard.apply(1.0), 0.5);
+        // the ACES fit reaches white at a finite exposure
+        assert_eq!(ToneMapping::AcesFilmic.apply(100.0), 1.0);
+        assert!((ToneMapping::AcesFilmic.apply(0.18) - 0.267).abs() < 1e-3);
+    }
+
+    fn scene() -> Scene {
+        let patches =
+            crate::surface::BezierSurface::parse_patches(include_str!("../assets/points.txt"));
+        Scene::new(patches.unwrap())
+    }
+
+    #[test]
+    fn renders_into_an_empty_buffer() {
+        let scene = scene();
+        let options = RenderOptions::default();
+        render(&scene, &options, &mut [], 0, 100);
+        render(&scene, &options, &mut [], 100, 0);
+        render(&scene, &options, &mut [], 0, 0);
+    }
+
+    #[test]
+    fn threads_render_the_same_image() {
+        let scene = scene();
+        let image = |threads| {
+            let options = RenderOptions {
+                threads,
+                ..RenderOptions::default()
+            };
+            let mut buffer = vec![0; 64 * 48 * 4];
+            render(&scene, &options, &mut buffer, 64, 48);
+            buffer
+        };
+        let single = image(1);
+        assert!(single.chunks_exact(4).any(|px| px[3] == 255));
+        assert_eq!(image(4), single);
+    }
+}
@@ -1,38 +1,69 @@
 use std::{
-    f32::consts::{FRAC_PI_2, PI},
     fs::File,
-    io::Read,
-    path::PathBuf,
-    str::FromStr,
+    io::{BufWriter, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use eframe::egui::{Color32, Painter, Stroke};
+use eframe::egui::{Color32, Painter, Pos2, Stroke};
 
 use crate::{
+    camera::Camera,
     canvas::Canvas,
     color::Color,
-    light::Light,
+    export::{
+        gltf, obj,
+        stl::{self, StlOptions},
+    },
+    import,
+    light::{Light, LightKind, Lighting},
     material::{Coloring, Material},
     mesh::Mesh,
-    point::Point3,
+    point::{Point3, Vector3},
+    render::RenderOptions,
+    shadow::ShadowMaps,
     surface::BezierSurface,
     texture::Texture,
+    transform::{Matrix4, Quaternion},
 };
 
+/// Control point of one of the scene patches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointHandle {
+    pub patch: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+mod file;
+
 pub struct Scene {
+    pub camera: Camera,
     pub material: Material,
-    pub light: Light,
+    pub lights: Vec<Light>,
+    /// ambient light reaching every point of the surface
+    pub ambient: Color,
     pub is_animating_surface: bool,
-    surface: BezierSurface,
+    patches: Vec<BezierSurface>,
     mesh: Mesh,
     resolution: usize,
-    rot_ox: f32,
-    rot_oz: f32,
+    /// rotation of the surface, which itself stays in model space
+    orientation: Quaternion,
+    /// shadow maps of the lights as of the last frame
+    shadows: Mutex<ShadowMaps>,
 }
 
 impl Scene {
+    /// Loads patches from a points file (`.bpt` and `.patches` are imported),
+    /// or a whole scene from a `.json` scene file
     pub fn from_file(name: Option<String>) -> Result<Self, String> {
-        let points_str = if let Some(name) = name {
+        if let Some(name) = &name
+            && name.ends_with(".json")
+        {
+            return Self::open(Path::new(name)).map(|(scene, _)| scene);
+        }
+
+        let points_str = if let Some(name) = &name {
             let mut f = File::open(name).map_err(|e| e.to_string())?;
             let mut buf = String::new();
             f.read_to_string(&mut buf).map_err(|e| e.to_string())?;
@@ -41,61 +72,62 @@ impl Scene {
             eprintln!("No points file provided, using default.");
             include_str!("../assets/points.txt").to_string()
         };
-        let surface = BezierSurface::from_str(&points_str)?;
+        let extension = name
+            .as_deref()
+            .and_then(|n| Path::new(n).extension())
+            .and_then(|e| e.to_str());
+        let patches = match extension {
+            Some("bpt") => import::parse_bpt(&points_str)?,
+            Some("patches") => import::parse_indexed_patches(&points_str)?,
+            _ => BezierSurface::parse_patches(&points_str)?,
+        };
+        Ok(Self::new(patches))
+    }
+
+    /// Scene with default material, white ambient light and a point light, viewed from the default angle
+    pub fn new(patches: Vec<BezierSurface>) -> Self {
         let resolution = 30;
-        let mesh = surface.triangulate(resolution);
+        let mesh = Mesh::from_patches(&patches, resolution);
+        let is_animating_surface = patches.len() == 1;
 
-        let mut s = Self {
-            surface,
+        Self {
+            camera: Camera::default(),
+            patches,
             mesh,
-            light: Light::new(Point3::new(-600.0, 700.0, 300.0), Color::new(1.0, 1.0, 1.0)),
+            lights: vec![Light::new(
+                Point3::new(-600.0, 700.0, 300.0),
+                Color::new(1.0, 1.0, 1.0),
+            )],
+            ambient: Color::new(1.0, 1.0, 1.0),
             material: Material::default(),
-            is_animating_surface: true,
+            is_animating_surface,
             resolution,
-            rot_ox: 0.0,
-            rot_oz: 0.0,
-        };
-
-        let rot_ox = 104.0f32.to_radians();
-        let rot_oz = 10.0f32.to_radians();
-
-        s.rotate_ox(rot_ox);
-        s.rotate_oz(rot_oz);
-        Ok(s)
+            orientation: Quaternion::from_euler(104.0f32.to_radians(), 0.0, 10.0f32.to_radians()),
+            shadows: Mutex::default(),
+        }
     }
 
-    pub fn rot_ox(&self) -> f32 {
-        self.rot_ox
+    pub fn orientation(&self) -> Quaternion {
+        self.orientation
     }
 
-    pub fn rot_oz(&self) -> f32 {
-        self.rot_oz
+    pub fn set_orientation(&mut self, q: Quaternion) {
+        self.orientation = q.normalized();
+        self.shadows.get_mut().unwrap().invalidate();
     }
 
-    pub fn rotate_ox(&mut self, delta: f32) {
-        let mut new_rot = self.rot_ox + delta;
-        if new_rot < -FRAC_PI_2 {
-            new_rot += PI;
-        } else if new_rot > FRAC_PI_2 {
-            new_rot -= PI;
-        }
-
-        self.rot_ox = new_rot;
-        self.surface.rotate_ox(delta);
-        self.mesh.rotate_ox(delta);
+    /// Rotation as angles around OX, OY and OZ, see [`Quaternion::to_euler`]
+    pub fn rotation(&self) -> (f32, f32, f32) {
+        self.orientation.to_euler()
     }
 
-    pub fn rotate_oz(&mut self, delta: f32) {
-        let mut new_rot = self.rot_oz + delta;
-        if new_rot < -FRAC_PI_2 {
-            new_rot += PI;
-        } else if new_rot > FRAC_PI_2 {
-            new_rot -= PI;
-        }
+    pub fn set_rotation(&mut self, ox: f32, oy: f32, oz: f32) {
+        self.set_orientation(Quaternion::from_euler(ox, oy, oz));
+    }
 
-        self.rot_oz = new_rot;
-        self.surface.rotate_oz(delta);
-        self.mesh.rotate_oz(delta);
+    /// Transform from model to world space
+    pub(crate) fn model_matrix(&self) -> Matrix4 {
+        Matrix4::rotation(self.orientation)
     }
 
     pub fn mesh_resolution(&self) -> usize {
@@ -109,74 +141,320 @@ impl Scene {
         }
     }
 
-    pub fn set_material_color(&mut self, color: [f32; 3]) {
-        self.material.coloring = Coloring::Solid(Color::from_slice(color));
+    /// Returns the replaced coloring
+    pub fn set_material_color(&mut self, color: [f32; 3]) -> Coloring {
+        let solid = Coloring::Solid(Color::from_slice(color));
+        std::mem::replace(&mut self.material.coloring, solid)
     }
 
-    pub fn light_color(&self) -> [f32; 3] {
-        self.light.color.as_slice()
+    pub fn light_color(&self, i: usize) -> [f32; 3] {
+        self.lights[i].color.as_slice()
     }
 
-    pub fn set_light_color(&mut self, color: [f32; 3]) {
-        self.light.color = Color::from_slice(color);
+    pub fn set_light_color(&mut self, i: usize, color: [f32; 3]) {
+        self.lights[i].color = Color::from_slice(color);
     }
 
-    pub fn set_texture(&mut self, path: PathBuf) {
-        if let Ok(img) = image::open(path) {
-            let img = img.to_rgba8();
-            let texture = Texture::from_img(img);
-            self.material.coloring = Coloring::Texture(texture);
-        }
+    pub fn ambient_color(&self) -> [f32; 3] {
+        self.ambient.as_slice()
     }
 
-    pub fn set_normal_map(&mut self, path: PathBuf) {
-        if let Ok(img) = image::open(path) {
-            let img = img.to_rgba8();
-            let texture = Texture::from_img(img);
-            self.material.normal_map = Some(texture);
-        }
+    pub fn set_ambient_color(&mut self, color: [f32; 3]) {
+        self.ambient = Color::from_slice(color);
+    }
+
+    pub fn advance_light_animations(&mut self, dt: f32) {
+        self.lights.iter_mut().for_each(|l| l.advance_animation(dt));
+    }
+
+    /// Returns the replaced coloring
+    pub fn set_texture(&mut self, path: PathBuf) -> Result<Coloring, String> {
+        let texture = Coloring::Texture(Texture::open(&path)?);
+        Ok(std::mem::replace(&mut self.material.coloring, texture))
+    }
+
+    /// Returns the replaced normal map
+    pub fn set_normal_map(&mut self, path: PathBuf) -> Result<Option<Texture>, String> {
+        let texture = Texture::open_normal_map(&path)?;
+        Ok(self.material.normal_map.replace(texture))
     }
 
     pub fn set_mesh_resolution(&mut self, res: usize) {
         self.resolution = res;
-        self.mesh = self.surface.triangulate(res);
+        self.update_mesh();
     }
 
-    pub fn draw_fillings(&self, canvas: &mut Canvas, draw_normals: bool) {
-        self.mesh
-            .draw_fillings(canvas, &self.light, &self.material, draw_normals);
+    /// Triangulates the patches again after they changed
+    fn update_mesh(&mut self) {
+        self.mesh = Mesh::from_patches(&self.patches, self.resolution);
+        self.shadows.get_mut().unwrap().invalidate();
     }
 
-    pub fn draw_outlines(&self, painter: &Painter) {
-        self.mesh.draw_outlines(painter);
+    pub fn export_obj(&self, path: PathBuf) -> Result<(), String> {
+        let f = File::create(path).map_err(|e| e.to_string())?;
+        obj::write_obj(&self.mesh, &mut BufWriter::new(f)).map_err(|e| e.to_string())
     }
 
-    pub fn draw_light_pos(&self, painter: &Painter) {
-        let stroke = Stroke::new(3.0, Color32::YELLOW);
-        let pos = self
-            .light
-            .pos
-            .to_viewport_center(painter.ctx())
-            .projection();
-
-        painter.circle_filled(pos, 6.0, stroke.color);
-        painter.line(
-            vec![
-                pos,
-                Point3::origin()
-                    .to_viewport_center(painter.ctx())
-                    .projection(),
-            ],
-            stroke,
+    pub fn export_stl(&self, path: PathBuf, options: &StlOptions) -> Result<(), String> {
+        let f = File::create(path).map_err(|e| e.to_string())?;
+        stl::write_stl(&self.mesh, options, &mut BufWriter::new(f)).map_err(|e| e.to_string())
+    }
+
+    /// Exports the mesh with its material, as binary glTF if the extension is `.glb`
+    pub fn export_gltf(&self, path: PathBuf) -> Result<(), String> {
+        let binary = path.extension().is_some_and(|e| e == "glb");
+        let f = File::create(path).map_err(|e| e.to_string())?;
+        gltf::write_gltf(&self.mesh, &self.material, binary, &mut BufWriter::new(f))
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn draw_fillings(&self, canvas: &mut Canvas, options: &RenderOptions) {
+        let world = self.mesh.transformed(&self.model_matrix());
+        let mut shadows = self.shadows.lock().unwrap();
+        let shadows = if options.shadows.enabled {
+            let threads = options.threads;
+            shadows.update(&self.lights, world.triangles(), &options.shadows, threads)
+        } else {
+            &[]
+        };
+        world.draw_fillings(
+            canvas,
+            &self.camera,
+            &Lighting {
+                ambient: self.ambient,
+                lights: &self.lights,
+            },
+            shadows,
+            &self.material,
+            options,
         );
     }
 
-    pub fn draw_points(&self, painter: &Painter) {
-        self.surface.draw_points(painter);
+    /// Projection of world space points onto the egui viewport,
+    /// matching the image of the canvas
+    pub fn viewport_projection<'a>(
+        &'a self,
+        canvas: &'a Canvas,
+    ) -> impl Fn(Point3) -> Option<Pos2> + 'a {
+        let (w, h) = (canvas.width() as f32, canvas.height() as f32);
+        move |p| {
+            self.camera
+                .project(p, w, h)
+                .map(|s| canvas.to_viewport(s.projection()))
+        }
+    }
+
+    /// Same as [`Scene::viewport_projection`] for model space points
+    fn model_projection<'a>(&'a self, canvas: &'a Canvas) -> impl Fn(Point3) -> Option<Pos2> + 'a {
+        let (w, h) = (canvas.width() as f32, canvas.height() as f32);
+        let model_view = self.camera.view_matrix() * self.model_matrix();
+        move |p| {
+            self.camera
+                .project_view(model_view.transform_point(p), w, h)
+                .map(|s| canvas.to_viewport(s.projection()))
+        }
+    }
+
+    pub fn draw_outlines(&self, painter: &Painter, canvas: &Canvas) {
+        let project = self.model_projection(canvas);
+        self.mesh.draw_outlines(painter, project);
+    }
+
+    /// Marks the lights with their direction, the `selected` one larger
+    pub fn draw_lights(&self, painter: &Painter, canvas: &Canvas, selected: usize) {
+        let stroke = Stroke::new(3.0, Color32::YELLOW);
+        let project = self.viewport_projection(canvas);
+        for (i, light) in self.lights.iter().enumerate() {
+            let radius = if i == selected { 6.0 } else { 4.0 };
+            // directional lights are drawn coming from afar towards the origin
+            let (pos, towards) = match light.kind {
+                LightKind::Point => (light.pos, Point3::origin()),
+                LightKind::Spot { .. } => (light.pos, light.pos + light.dir.normalized() * 150.0),
+                LightKind::Directional => {
+                    let dir = light.dir.normalized();
+                    (
+                        Point3::origin() + dir * -500.0,
+                        Point3::origin() + dir * -350.0,
+                    )
+                }
+            };
+            let Some(pos) = project(pos) else {
+                continue;
+            };
+
+            painter.circle_filled(pos, radius, stroke.color);
+            if let Some(towards) = project(towards) {
+                painter.line(vec![pos, towards], stroke);
+            }
+        }
+    }
+
+    pub fn draw_points(&self, painter: &Painter, canvas: &Canvas) {
+        let project = self.model_projection(canvas);
+        self.patches
+            .iter()
+            .for_each(|p| p.draw_points(painter, &project));
+    }
+
+    /// Whether the surface can be animated: the animated control point of a patch
+    /// may lie on a seam with its neighbours, so only single patch surfaces are
+    pub fn can_animate_surface(&self) -> bool {
+        self.patches.len() == 1
+    }
+
+    /// Control point in model space
+    pub fn point(&self, handle: PointHandle) -> Point3 {
+        self.patches[handle.patch].point(handle.row, handle.col)
+    }
+
+    /// Control point as placed in the world
+    pub fn world_point(&self, handle: PointHandle) -> Point3 {
+        self.model_matrix().transform_point(self.point(handle))
+    }
+
+    /// Finds the control point drawn closest to `pos`, within `radius` pixels,
+    /// returns it together with the coincident points of other patches so that
+    /// moving them keeps shared edges together
+    pub fn pick_point(&self, pos: Pos2, canvas: &Canvas, radius: f32) -> Option<Vec<PointHandle>> {
+        let project = self.model_projection(canvas);
+        let mut closest = None;
+        let mut closest_dist = radius;
+        for (patch, surface) in self.patches.iter().enumerate() {
+            let (n, m) = surface.degree();
+            for row in 0..=m {
+                for col in 0..=n {
+                    let Some(p) = project(surface.point(row, col)) else {
+                        continue;
+                    };
+                    let dist = p.distance(pos);
+                    if dist <= closest_dist {
+                        closest_dist = dist;
+                        closest = Some(PointHandle { patch, row, col });
+                    }
+                }
+            }
+        }
+
+        let picked = self.point(closest?);
+        let mut handles = Vec::new();
+        for (patch, surface) in self.patches.iter().enumerate() {
+            let (n, m) = surface.degree();
+            for row in 0..=m {
+                for col in 0..=n {
+                    if surface.point(row, col) == picked {
+                        handles.push(PointHandle { patch, row, col });
+                    }
+                }
+            }
+        }
+        Some(handles)
+    }
+
+    /// Moves the points by `delta` given in model space
+    pub fn move_points(&mut self, handles: &[PointHandle], delta: Vector3) {
+        for h in handles {
+            self.patches[h.patch].move_point(h.row, h.col, delta);
+        }
+        self.update_mesh();
     }
 
     pub fn advance_surface_animation(&mut self, dt: f32) {
-        self.surface.advance_animation(dt);
-        self.mesh = self.surface.triangulate(self.resolution);
+        if !self.can_animate_surface() {
+            return;
+        }
+        self.patches
+            .iter_mut()
+            .for_each(|p| p.advance_animation(dt));
+        self.update_mesh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::{pos2, vec2};
+
+    /// Four bilinear patches of a flat 200 x 200 square, all sharing its centre
+    fn quarters() -> Scene {
+        let patch = |x: f32, y: f32| {
+            format!(
+                "1 1\n{x} {y} 0\n{} {y} 0\n{x} {} 0\n{} {} 0\n",
+                x + 100.0,
+                y + 100.0,
+                x + 100.0,
+                y + 100.0
+            )
+        };
+        let points = [(-100.0, -100.0), (0.0, -100.0), (-100.0, 0.0), (0.0, 0.0)]
+            .map(|(x, y)| patch(x, y))
+            .concat();
+        Scene::new(BezierSurface::parse_patches(&points).unwrap())
+    }
+
+    /// Position of a control point in the viewport of `canvas`
+    fn project(scene: &Scene, h: PointHandle, canvas: &Canvas) -> Pos2 {
+        scene.model_projection(canvas)(scene.point(h)).unwrap()
+    }
+
+    const CENTRE: PointHandle = PointHandle {
+        patch: 0,
+        row: 1,
+        col: 1,
+    };
+
+    #[test]
+    fn picks_coincident_points_together() {
+        let scene = quarters();
+        let canvas = Canvas::new(800, 800);
+        let mut handles = scene
+            .pick_point(
+                project(&scene, CENTRE, &canvas) + vec2(2.0, -1.0),
+                &canvas,
+                8.0,
+            )
+            .unwrap();
+        handles.sort_by_key(|h| h.patch);
+        let expected = [(0, 1, 1), (1, 1, 0), (2, 0, 1), (3, 0, 0)]
+            .map(|(patch, row, col)| PointHandle { patch, row, col });
+        assert_eq!(handles, expected);
+
+        let corner = PointHandle {
+            patch: 0,
+            row: 0,
+            col: 0,
+        };
+        let handles = scene
+            .pick_point(project(&scene, corner, &canvas), &canvas, 8.0)
+            .unwrap();
+        assert_eq!(handles, [corner]);
+        assert!(scene.pick_point(pos2(-50.0, -50.0), &canvas, 8.0).is_none());
+    }
+
+    #[test]
+    fn moves_shared_corners_together() {
+        let mut scene = quarters();
+        let before = scene.point(CENTRE);
+        let canvas = Canvas::new(800, 800);
+        let handles = scene
+            .pick_point(project(&scene, CENTRE, &canvas), &canvas, 8.0)
+            .unwrap();
+
+        let delta = Vector3::new(10.0, 0.0, 50.0);
+        scene.move_points(&handles, delta);
+        for &h in &handles {
+            assert_eq!(scene.point(h), before + delta);
+        }
+        // the mesh stays closed around the moved corner
+        let near = |p: Point3| {
+            scene
+                .mesh
+                .triangles()
+                .iter()
+                .flat_map(|t| [t.p0.pos(), t.p1.pos(), t.p2.pos()])
+                .filter(|&q| (q - p).length() < 1e-3)
+                .count()
+        };
+        assert!(near(before + delta) >= 4);
+        assert_eq!(near(before), 0);
     }
 }
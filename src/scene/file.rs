@@ -0,0 +1,1034 @@
+//! Versioned JSON scene files holding the control points together with
+//! the material, lights, view and visibility settings
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    light::{Attenuation, Light, LightKind},
+    material::{Coloring, Material, ShadingModel},
+    point::{Point3, Vector3},
+    render::Visible,
+    surface::BezierSurface,
+    texture::{Texture, TextureFilter},
+    transform::{Matrix4, Quaternion},
+};
+
+use super::Scene;
+
+/// Version written to new files, older versions are still read,
+/// version 2 added the camera, version 3 replaced the rotation angles
+/// with an orientation quaternion, version 4 replaced the light with a list
+/// of lights of different kinds, version 5 added the shading model, version 6
+/// added the ambient light and the attenuation of the lights, version 7 added
+/// the texture filters
+const VERSION: u32 = 7;
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SceneFile {
+    version: u32,
+    patches: Vec<PatchFile>,
+    resolution: usize,
+    /// rotation of the surface as a unit quaternion [w, x, y, z]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    orientation: Option<[f32; 4]>,
+    /// rotations in degrees applied to the points, up to version 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rot_ox: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rot_oz: Option<f32>,
+    is_animating_surface: bool,
+    material: MaterialFile,
+    #[serde(default)]
+    lights: Vec<LightFile>,
+    /// the only light, up to version 3
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    light: Option<LegacyLightFile>,
+    /// white up to version 5
+    #[serde(default)]
+    ambient: Option<[f32; 3]>,
+    #[serde(default)]
+    camera: Option<CameraFile>,
+    #[serde(default)]
+    visible: Visible,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PatchFile {
+    /// rows of control points as in the points file, in model space,
+    /// up to version 2 before the rotation
+    points: Vec<Vec<[f32; 3]>>,
+    weights: Vec<Vec<f32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MaterialFile {
+    /// `None` for a textured surface
+    color: Option<[f32; 3]>,
+    /// relative paths start at the directory of the scene file, files inside it
+    /// are written relative to it and the others as absolute paths
+    texture: Option<PathBuf>,
+    normal_map: Option<PathBuf>,
+    /// bilinear, the default filter, up to version 6
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    texture_filter: Option<TextureFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normal_map_filter: Option<TextureFilter>,
+    /// no ambient light was reflected up to version 5
+    #[serde(default)]
+    ka: f32,
+    kd: f32,
+    ks: f32,
+    m: i32,
+    /// Phong up to version 4
+    #[serde(default)]
+    shading: ShadingFile,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(tag = "model", rename_all = "kebab-case")]
+enum ShadingFile {
+    #[default]
+    Phong,
+    BlinnPhong,
+    CookTorrance {
+        metallic: f32,
+        roughness: f32,
+    },
+}
+
+impl From<ShadingModel> for ShadingFile {
+    fn from(s: ShadingModel) -> Self {
+        match s {
+            ShadingModel::Phong => Self::Phong,
+            ShadingModel::BlinnPhong => Self::BlinnPhong,
+            ShadingModel::CookTorrance {
+                metallic,
+                roughness,
+            } => Self::CookTorrance {
+                metallic,
+                roughness,
+            },
+        }
+    }
+}
+
+impl TryFrom<ShadingFile> for ShadingModel {
+    type Error = String;
+
+    fn try_from(s: ShadingFile) -> Result<Self, String> {
+        Ok(match s {
+            ShadingFile::Phong => Self::Phong,
+            ShadingFile::BlinnPhong => Self::BlinnPhong,
+            ShadingFile::CookTorrance {
+                metallic,
+                roughness,
+            } => {
+                if !((0.0..=1.0).contains(&metallic) && (0.0..=1.0).contains(&roughness)) {
+                    return Err("metallic and roughness should be in 0..=1".into());
+                }
+                Self::CookTorrance {
+                    metallic,
+                    roughness,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LightFile {
+    kind: LightKindFile,
+    pos: [f32; 3],
+    dir: [f32; 3],
+    color: [f32; 3],
+    intensity: f32,
+    /// constant, linear and quadratic coefficients, no falloff up to version 5
+    #[serde(default)]
+    attenuation: Option<[f32; 3]>,
+    t: f32,
+    is_animating: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LightKindFile {
+    Point,
+    Directional,
+    /// cone angles in degrees
+    Spot {
+        inner: f32,
+        outer: f32,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct LegacyLightFile {
+    pos: [f32; 3],
+    color: [f32; 3],
+    t: f32,
+    is_animating: bool,
+    /// reflector exponent, 0 for a point light, otherwise shining towards
+    /// the origin with I = I0 * cos^r(theta)
+    r: i32,
+}
+
+impl From<&Light> for LightFile {
+    fn from(l: &Light) -> Self {
+        let kind = match l.kind {
+            LightKind::Point => LightKindFile::Point,
+            LightKind::Directional => LightKindFile::Directional,
+            LightKind::Spot { inner, outer } => LightKindFile::Spot {
+                inner: inner.to_degrees(),
+                outer: outer.to_degrees(),
+            },
+        };
+        Self {
+            kind,
+            pos: [l.pos.x, l.pos.y, l.pos.z],
+            dir: [l.dir.x, l.dir.y, l.dir.z],
+            color: l.color.as_slice(),
+            intensity: l.intensity,
+            attenuation: Some([
+                l.attenuation.constant,
+                l.attenuation.linear,
+                l.attenuation.quadratic,
+            ]),
+            t: l.t,
+            is_animating: l.is_animating,
+        }
+    }
+}
+
+impl TryFrom<LightFile> for Light {
+    type Error = String;
+
+    fn try_from(l: LightFile) -> Result<Self, String> {
+        let kind = match l.kind {
+            LightKindFile::Point => LightKind::Point,
+            LightKindFile::Directional => LightKind::Directional,
+            LightKindFile::Spot { inner, outer } => {
+                if !(0.0 <= inner && inner <= outer && outer <= 90.0) {
+                    return Err("spotlight angles should satisfy 0 <= inner <= outer <= 90".into());
+                }
+                LightKind::Spot {
+                    inner: inner.to_radians(),
+                    outer: outer.to_radians(),
+                }
+            }
+        };
+        if !(l.intensity >= 0.0 && l.intensity.is_finite()) {
+            return Err("light intensity should be a nonnegative number".into());
+        }
+        let [constant, linear, quadratic] = l.attenuation.unwrap_or([1.0, 0.0, 0.0]);
+        if ![constant, linear, quadratic]
+            .iter()
+            .all(|c| (0.0..=f32::MAX).contains(c))
+        {
+            return Err("light attenuation coefficients should be nonnegative".into());
+        }
+        if constant == 0.0 && linear == 0.0 && quadratic == 0.0 {
+            return Err("light attenuation coefficients should not all be zero".into());
+        }
+        let [x, y, z] = l.dir;
+        let dir = Vector3::new(x, y, z);
+        if !(dir.length() > 1e-6 && dir.length().is_finite()) {
+            return Err("light direction should be a nonzero vector".into());
+        }
+        let [x, y, z] = l.pos;
+        Ok(Self {
+            kind,
+            dir: dir.normalized(),
+            intensity: l.intensity,
+            attenuation: Attenuation {
+                constant,
+                linear,
+                quadratic,
+            },
+            t: l.t,
+            is_animating: l.is_animating,
+            ..Light::new(Point3::new(x, y, z), check_color(l.color)?)
+        })
+    }
+}
+
+impl TryFrom<LegacyLightFile> for Light {
+    type Error = String;
+
+    /// A reflector becomes a spotlight aimed at the origin, fading out where
+    /// cos^r(theta) drops to 5%
+    fn try_from(l: LegacyLightFile) -> Result<Self, String> {
+        let [x, y, z] = l.pos;
+        let mut light = Light {
+            t: l.t,
+            is_animating: l.is_animating,
+            ..Light::new(Point3::new(x, y, z), check_color(l.color)?)
+        };
+        if l.r > 0 {
+            light.kind = LightKind::Spot {
+                inner: 0.0,
+                outer: 0.05f32.powf(1.0 / l.r as f32).acos(),
+            };
+        }
+        Ok(light)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CameraFile {
+    position: [f32; 3],
+    target: [f32; 3],
+    up: [f32; 3],
+    /// vertical field of view in degrees
+    fov: f32,
+    near: f32,
+    far: f32,
+}
+
+impl From<&Camera> for CameraFile {
+    fn from(c: &Camera) -> Self {
+        Self {
+            position: [c.position.x, c.position.y, c.position.z],
+            target: [c.target.x, c.target.y, c.target.z],
+            up: [c.up.x, c.up.y, c.up.z],
+            fov: c.fov.to_degrees(),
+            near: c.near,
+            far: c.far,
+        }
+    }
+}
+
+impl TryFrom<CameraFile> for Camera {
+    type Error = String;
+
+    fn try_from(c: CameraFile) -> Result<Self, String> {
+        if !(1.0..180.0).contains(&c.fov) {
+            return Err("camera field of view should be in 1..180 degrees".into());
+        }
+        if !(0.0 < c.near && c.near < c.far) {
+            return Err("camera planes should satisfy 0 < near < far".into());
+        }
+        let [x, y, z] = c.position;
+        let position = Point3::new(x, y, z);
+        let [x, y, z] = c.target;
+        let target = Point3::new(x, y, z);
+        let [x, y, z] = c.up;
+        let up = Vector3::new(x, y, z);
+        if (target - position).cross(up).length() < 1e-6 {
+            return Err("camera up direction should not be parallel to the view".into());
+        }
+        Ok(Self {
+            position,
+            target,
+            up,
+            fov: c.fov.to_radians(),
+            near: c.near,
+            far: c.far,
+        })
+    }
+}
+
+fn check_color(color: [f32; 3]) -> Result<Color, String> {
+    if color.iter().all(|c| (0.0..=1.0).contains(c)) {
+        Ok(Color::from_slice(color))
+    } else {
+        Err(format!(
+            "color components should be in 0..=1, got {color:?}"
+        ))
+    }
+}
+
+/// `path` relative to `dir` if it lies inside, otherwise absolute,
+/// so that it resolves to the same file after joining it to `dir`
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let absolute = |p: &Path| {
+        std::fs::canonicalize(p)
+            .or_else(|_| std::path::absolute(p))
+            .unwrap_or_else(|_| p.to_path_buf())
+    };
+    let path = absolute(path);
+    match path.strip_prefix(absolute(dir)) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path,
+    }
+}
+
+impl Scene {
+    /// Writes the scene with the viewport `visible` settings as JSON
+    pub fn save(&self, path: &Path, visible: &Visible) -> Result<(), String> {
+        let patches = self
+            .patches
+            .iter()
+            .map(|patch| PatchFile {
+                points: patch
+                    .rest_points()
+                    .into_iter()
+                    .map(|row| row.into_iter().map(|p| [p.x, p.y, p.z]).collect())
+                    .collect(),
+                weights: patch.weights().to_vec(),
+            })
+            .collect();
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let relative = |texture: &Path| relative_path(texture, dir);
+        let mat = &self.material;
+        let (color, texture, texture_filter) = match &mat.coloring {
+            Coloring::Solid(c) => (Some(c.as_slice()), None, None),
+            Coloring::Texture(t) => (None, t.path.as_deref().map(&relative), Some(t.filter)),
+        };
+        let q = self.orientation;
+        let file = SceneFile {
+            version: VERSION,
+            patches,
+            resolution: self.resolution,
+            orientation: Some([q.w, q.x, q.y, q.z]),
+            rot_ox: None,
+            rot_oz: None,
+            is_animating_surface: self.is_animating_surface,
+            material: MaterialFile {
+                color,
+                texture,
+                normal_map: mat
+                    .normal_map
+                    .as_ref()
+                    .and_then(|t| t.path.as_deref().map(&relative)),
+                texture_filter,
+                normal_map_filter: mat.normal_map.as_ref().map(|t| t.filter),
+                ka: mat.ka,
+                kd: mat.kd,
+                ks: mat.ks,
+                m: mat.m,
+                shading: mat.shading.into(),
+            },
+            lights: self.lights.iter().map(LightFile::from).collect(),
+            light: None,
+            ambient: Some(self.ambient.as_slice()),
+            camera: Some((&self.camera).into()),
+            visible: *visible,
+        };
+
+        let f = File::create(path).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(BufWriter::new(f), &file).map_err(|e| e.to_string())
+    }
+
+    /// Reads a scene written by [`Scene::save`] together with its visibility settings
+    pub fn open(path: &Path) -> Result<(Self, Visible), String> {
+        let err = |e: String| format!("{}: {e}", path.display());
+        let s = std::fs::read_to_string(path).map_err(|e| err(e.to_string()))?;
+
+        let header: Header = serde_json::from_str(&s).map_err(|e| err(e.to_string()))?;
+        if header.version > VERSION {
+            return Err(err(format!(
+                "scene file version {} is newer than the supported {VERSION}",
+                header.version
+            )));
+        }
+        let file: SceneFile = serde_json::from_str(&s).map_err(|e| err(e.to_string()))?;
+
+        let visible = file.visible;
+        let scene = Self::from_scene_file(file, path.parent().unwrap_or(Path::new("")));
+        scene.map(|scene| (scene, visible)).map_err(err)
+    }
+
+    fn from_scene_file(file: SceneFile, dir: &Path) -> Result<Self, String> {
+        if file.patches.is_empty() {
+            return Err("scene should contain at least one patch".into());
+        }
+        if !(2..=50).contains(&file.resolution) {
+            return Err("resolution should be in 2..=50".into());
+        }
+
+        // up to version 2 the points were saved with the wrapped rotation angles
+        // undone, which left them flipped by 180° about OX whenever an angle had
+        // wrapped, so the rotation is applied to the points instead
+        let (orientation, legacy_rotation) = match file.orientation {
+            Some(q) if q.iter().all(|c| c.is_finite()) && q.iter().any(|&c| c != 0.0) => {
+                let [w, x, y, z] = q;
+                (Quaternion { w, x, y, z }, Matrix4::identity())
+            }
+            Some(_) => return Err("orientation should be a nonzero quaternion".into()),
+            None => {
+                let ox = file.rot_ox.unwrap_or_default().to_radians();
+                let oz = file.rot_oz.unwrap_or_default().to_radians();
+                let q = Quaternion::from_euler(ox, 0.0, oz);
+                (Quaternion::identity(), Matrix4::rotation(q))
+            }
+        };
+
+        let patches = file
+            .patches
+            .into_iter()
+            .enumerate()
+            .map(|(i, patch)| {
+                let points = patch
+                    .points
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .map(|[x, y, z]| legacy_rotation.transform_point(Point3::new(x, y, z)))
+                            .collect()
+                    })
+                    .collect();
+                BezierSurface::new(points, patch.weights)
+                    .map_err(|e| format!("patch {}: {e}", i + 1))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let m = file.material;
+        for (name, k) in [("ka", m.ka), ("kd", m.kd), ("ks", m.ks)] {
+            if !(0.0..=1.0).contains(&k) {
+                return Err(format!("material {name} should be in 0..=1, got {k}"));
+            }
+        }
+        if m.m <= 0 {
+            return Err(format!("material m should be positive, got {}", m.m));
+        }
+        let load = |open: fn(&Path) -> Result<Texture, String>,
+                    path: PathBuf,
+                    filter: Option<TextureFilter>| {
+            let mut texture = open(&dir.join(path))?;
+            texture.filter = filter.unwrap_or_default();
+            Ok::<_, String>(texture)
+        };
+        let coloring = match (m.texture, m.color) {
+            (Some(path), _) => Coloring::Texture(load(Texture::open, path, m.texture_filter)?),
+            (None, Some(color)) => Coloring::Solid(check_color(color)?),
+            // texture not loaded from a file
+            (None, None) => Material::default().coloring,
+        };
+        let normal_map = m
+            .normal_map
+            .map(|path| load(Texture::open_normal_map, path, m.normal_map_filter))
+            .transpose()?;
+
+        let lights = match file.light {
+            Some(legacy) => vec![Light::try_from(legacy)?],
+            None => file
+                .lights
+                .into_iter()
+                .enumerate()
+                .map(|(i, l)| Light::try_from(l).map_err(|e| format!("light {}: {e}", i + 1)))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let mut scene = Self::new(patches);
+        scene.set_orientation(orientation);
+        scene.material = Material {
+            coloring,
+            normal_map,
+            ka: m.ka,
+            kd: m.kd,
+            ks: m.ks,
+            m: m.m,
+            shading: m.shading.try_into()?,
+        };
+        scene.lights = lights;
+        if let Some(ambient) = file.ambient {
+            scene.ambient = check_color(ambient)?;
+        }
+        scene.camera = file
+            .camera
+            .map(Camera::try_from)
+            .transpose()?
+            .unwrap_or_default();
+        scene.is_animating_surface = file.is_animating_surface && scene.can_animate_surface();
+        scene.set_mesh_resolution(file.resolution);
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_4;
+
+    use super::*;
+
+    /// Empty directory private to the test `name`, removed with its contents when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("bezier-surface-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn join(&self, path: &str) -> PathBuf {
+            self.0.join(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn open_json(name: &str, json: &str) -> Result<Scene, String> {
+        let dir = TempDir::new(name);
+        let path = dir.join("scene.json");
+        std::fs::write(&path, json).unwrap();
+        Scene::open(&path).map(|(scene, _)| scene)
+    }
+
+    fn write_png(path: &Path) {
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([200, 100, 50, 255]))
+            .save(path)
+            .unwrap();
+    }
+
+    fn solid(material: &Material) -> Option<[f32; 3]> {
+        match material.coloring {
+            Coloring::Solid(c) => Some(c.as_slice()),
+            Coloring::Texture(_) => None,
+        }
+    }
+
+    fn assert_near(a: Point3, b: Point3) {
+        assert!((a - b).length() < 1e-3, "{a:?} != {b:?}");
+    }
+
+    /// Bilinear patch as a points file and as stored in a scene file
+    const PATCH_POINTS: &str = "1 1\n0 0 0\n100 0 0\n0 100 0\n100 100 50\n";
+    const PATCH: &str = r#"{
+        "points": [[[0, 0, 0], [100, 0, 0]], [[0, 100, 0], [100, 100, 50]]],
+        "weights": [[1, 1], [1, 1]]
+    }"#;
+
+    /// Scene file of `version` with the given fields besides the patch
+    fn scene_json(version: u32, fields: &str) -> String {
+        format!(
+            r#"{{
+                "version": {version},
+                "patches": [{PATCH}],
+                "resolution": 10,
+                "is_animating_surface": false,
+                {fields}
+            }}"#
+        )
+    }
+
+    const SOLID: &str = r#""color": [0.5, 0.25, 1.0], "texture": null, "normal_map": null"#;
+
+    #[test]
+    fn round_trip() {
+        let patches = BezierSurface::parse_patches(include_str!("../../assets/points.txt"));
+        let mut scene = Scene::new(patches.unwrap());
+        scene.set_orientation(Quaternion::from_euler(0.3, -0.2, 1.1));
+        scene.set_mesh_resolution(12);
+        scene.material = Material {
+            coloring: Coloring::Solid(Color::new(0.2, 0.4, 0.6)),
+            ka: 0.3,
+            kd: 0.7,
+            ks: 0.2,
+            m: 20,
+            shading: ShadingModel::CookTorrance {
+                metallic: 0.5,
+                roughness: 0.25,
+            },
+            ..Material::default()
+        };
+        scene.ambient = Color::new(0.1, 0.2, 0.3);
+        let mut spot = Light::new(Point3::new(100.0, 200.0, 300.0), Color::new(1.0, 0.5, 0.0));
+        spot.kind = LightKind::Spot {
+            inner: 0.25,
+            outer: 0.5,
+        };
+        spot.intensity = 2.5;
+        spot.attenuation = Attenuation {
+            constant: 0.5,
+            linear: 1e-3,
+            quadratic: 1e-5,
+        };
+        spot.is_animating = false;
+        let mut sun = Light::new(Point3::new(0.0, 0.0, 1.0), Color::new(1.0, 1.0, 1.0));
+        sun.kind = LightKind::Directional;
+        sun.dir = Vector3::new(0.0, -1.0, 0.0);
+        scene.lights = vec![spot, sun];
+        scene.camera.position = Point3::new(100.0, 200.0, 900.0);
+        scene.camera.fov = FRAC_PI_4;
+        scene.is_animating_surface = false;
+        let visible = Visible {
+            polygon: true,
+            normals: true,
+            ..Visible::default()
+        };
+
+        let dir = TempDir::new("round-trip");
+        let path = dir.join("scene.json");
+        scene.save(&path, &visible).unwrap();
+        let (loaded, loaded_visible) = Scene::open(&path).unwrap();
+
+        assert_eq!(loaded_visible, visible);
+        assert_eq!(loaded.resolution, 12);
+        assert!(!loaded.is_animating_surface);
+        assert_eq!(
+            loaded.patches[0].rest_points(),
+            scene.patches[0].rest_points()
+        );
+        let (a, b) = (loaded.orientation(), scene.orientation());
+        assert!(
+            (a.w - b.w).abs() + (a.x - b.x).abs() + (a.y - b.y).abs() + (a.z - b.z).abs() < 1e-5
+        );
+
+        let (m, n) = (&loaded.material, &scene.material);
+        assert_eq!(solid(m), solid(n));
+        assert_eq!((m.ka, m.kd, m.ks, m.m), (n.ka, n.kd, n.ks, n.m));
+        assert_eq!(m.shading, n.shading);
+        assert_eq!(loaded.ambient, scene.ambient);
+
+        assert_eq!(loaded.lights.len(), 2);
+        for (a, b) in loaded.lights.iter().zip(&scene.lights) {
+            assert_eq!(a.color.as_slice(), b.color.as_slice());
+            assert_eq!((a.intensity, a.attenuation), (b.intensity, b.attenuation));
+            assert_eq!((a.pos, a.is_animating), (b.pos, b.is_animating));
+            assert!((a.dir - b.dir).length() < 1e-6);
+            match (a.kind, b.kind) {
+                (LightKind::Spot { inner, outer }, LightKind::Spot { inner: i, outer: o }) => {
+                    assert!((inner - i).abs() < 1e-6 && (outer - o).abs() < 1e-6)
+                }
+                (a, b) => assert_eq!(a, b),
+            }
+        }
+
+        assert_eq!(loaded.camera.position, scene.camera.position);
+        assert!((loaded.camera.fov - FRAC_PI_4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn lights_keep_their_positions() {
+        let mut scene = Scene::new(BezierSurface::parse_patches(PATCH_POINTS).unwrap());
+        let pos = Point3::new(-250.0, 120.0, 400.0);
+        let mut light = Light::new(pos, Color::new(1.0, 1.0, 1.0));
+        light.is_animating = false;
+        scene.lights = vec![light];
+
+        let dir = TempDir::new("light-positions");
+        let path = dir.join("scene.json");
+        scene.save(&path, &Visible::default()).unwrap();
+        let (mut loaded, _) = Scene::open(&path).unwrap();
+        loaded.advance_light_animations(0.5);
+        assert_eq!(loaded.lights[0].pos, pos);
+    }
+
+    #[test]
+    fn texture_paths_survive_saving_again() {
+        let dir = TempDir::new("texture-paths");
+        write_png(&dir.join("t.png"));
+        let mut scene = Scene::new(BezierSurface::parse_patches(PATCH_POINTS).unwrap());
+        scene.set_texture(dir.join("t.png")).unwrap();
+        scene.set_normal_map(dir.join("t.png")).unwrap();
+
+        let path = dir.join("scene.json");
+        scene.save(&path, &Visible::default()).unwrap();
+        let first = std::fs::read_to_string(&path).unwrap();
+        assert!(first.contains(r#""texture": "t.png""#), "{first}");
+        assert!(first.contains(r#""normal_map": "t.png""#), "{first}");
+
+        let (loaded, _) = Scene::open(&path).unwrap();
+        loaded.save(&path, &Visible::default()).unwrap();
+        let (loaded, _) = Scene::open(&path).unwrap();
+        assert!(matches!(loaded.material.coloring, Coloring::Texture(_)));
+        assert!(loaded.material.normal_map.is_some());
+
+        // saved elsewhere, the texture is referenced by its absolute path
+        let other_dir = TempDir::new("texture-paths-other");
+        let other = other_dir.join("scene.json");
+        loaded.save(&other, &Visible::default()).unwrap();
+        let (moved, _) = Scene::open(&other).unwrap();
+        let Coloring::Texture(t) = &moved.material.coloring else {
+            panic!("texture lost");
+        };
+        assert!(t.path.as_ref().unwrap().is_absolute());
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let json = scene_json(
+            1,
+            &format!(
+                r#""rot_ox": -76.0, "rot_oz": 10.0,
+                "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }},
+                "light": {{ "pos": [0, 0, 500], "color": [1, 1, 1], "t": 0.5,
+                            "is_animating": false, "r": 0 }}"#
+            ),
+        );
+        let scene = open_json("v1", &json).unwrap();
+
+        // the rotation is applied to the points, leaving them where they were shown
+        assert_eq!(scene.orientation(), Quaternion::identity());
+        let q = Quaternion::from_euler((-76.0f32).to_radians(), 0.0, 10.0f32.to_radians());
+        let shown = q.rotate(Vector3::new(100.0, 100.0, 50.0));
+        assert_near(
+            scene.patches[0].point(1, 1),
+            Point3::new(shown.x, shown.y, shown.z),
+        );
+
+        let m = &scene.material;
+        assert_eq!((m.ka, m.kd, m.ks, m.m), (0.0, 0.4, 0.6, 10));
+        assert_eq!(solid(m), Some([0.5, 0.25, 1.0]));
+        assert_eq!(scene.ambient, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(scene.camera, Camera::default());
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.lights[0].kind, LightKind::Point);
+        assert_eq!(scene.lights[0].attenuation, Attenuation::default());
+        assert_eq!(scene.lights[0].pos, Point3::new(0.0, 0.0, 500.0));
+        assert_eq!(scene.lights[0].t, 0.5);
+        assert_eq!(scene.resolution, 10);
+    }
+
+    #[test]
+    fn reads_version_2() {
+        let json = scene_json(
+            2,
+            &format!(
+                r#""rot_ox": 90.0, "rot_oz": 0.0,
+                "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }},
+                "light": {{ "pos": [0, 0, 500], "color": [1, 1, 1], "t": 0,
+                            "is_animating": true, "r": 0 }},
+                "camera": {{ "position": [0, 300, 800], "target": [0, 0, 0], "up": [0, 1, 0],
+                             "fov": 45, "near": 1, "far": 5000 }}"#
+            ),
+        );
+        let scene = open_json("v2", &json).unwrap();
+
+        assert_eq!(scene.orientation(), Quaternion::identity());
+        assert_near(
+            scene.patches[0].point(1, 1),
+            Point3::new(100.0, -50.0, 100.0),
+        );
+        assert_eq!(scene.camera.position, Point3::new(0.0, 300.0, 800.0));
+        assert_eq!((scene.camera.near, scene.camera.far), (1.0, 5000.0));
+    }
+
+    #[test]
+    fn reads_version_3() {
+        let json = scene_json(
+            3,
+            &format!(
+                r#""orientation": [0.0, 1.0, 0.0, 0.0],
+                "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }},
+                "light": {{ "pos": [0, 0, 500], "color": [1, 1, 1], "t": 0,
+                            "is_animating": true, "r": 8 }},
+                "camera": {{ "position": [0, 0, 800], "target": [0, 0, 0], "up": [0, 1, 0],
+                             "fov": 45, "near": 1, "far": 5000 }}"#
+            ),
+        );
+        let scene = open_json("v3", &json).unwrap();
+
+        let flipped = Quaternion {
+            w: 0.0,
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        assert_eq!(scene.orientation(), flipped);
+        assert_eq!(
+            scene.patches[0].point(1, 1),
+            Point3::new(100.0, 100.0, 50.0)
+        );
+        let LightKind::Spot { inner, outer } = scene.lights[0].kind else {
+            panic!("reflector should become a spotlight");
+        };
+        assert_eq!(inner, 0.0);
+        assert!((outer.cos().powi(8) - 0.05).abs() < 1e-4);
+        assert!((scene.camera.fov - FRAC_PI_4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reads_version_4() {
+        let json = scene_json(
+            4,
+            &format!(
+                r#""orientation": [1, 0, 0, 0],
+                "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }},
+                "lights": [
+                    {{ "kind": {{ "type": "directional" }}, "pos": [0, 0, 500],
+                       "dir": [0, 0, -2], "color": [1, 1, 1], "intensity": 0.5,
+                       "t": 0, "is_animating": false }},
+                    {{ "kind": {{ "type": "spot", "inner": 10, "outer": 20 }},
+                       "pos": [0, 300, 0], "dir": [0, -1, 0], "color": [0, 1, 0],
+                       "intensity": 1, "t": 0, "is_animating": true }}
+                ]"#
+            ),
+        );
+        let scene = open_json("v4", &json).unwrap();
+
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.lights[0].kind, LightKind::Directional);
+        assert_eq!(scene.lights[0].dir, Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(scene.lights[0].intensity, 0.5);
+        let LightKind::Spot { inner, outer } = scene.lights[1].kind else {
+            panic!("expected a spotlight");
+        };
+        assert!((inner.to_degrees() - 10.0).abs() < 1e-4);
+        assert!((outer.to_degrees() - 20.0).abs() < 1e-4);
+        assert!(
+            scene
+                .lights
+                .iter()
+                .all(|l| l.attenuation == Attenuation::default())
+        );
+        assert_eq!(scene.material.shading, ShadingModel::Phong);
+    }
+
+    #[test]
+    fn reads_version_5() {
+        let json = scene_json(
+            5,
+            &format!(
+                r#""orientation": [1, 0, 0, 0],
+                "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10,
+                    "shading": {{ "model": "cook-torrance", "metallic": 1, "roughness": 0.3 }} }}"#
+            ),
+        );
+        let scene = open_json("v5", &json).unwrap();
+
+        assert_eq!(
+            scene.material.shading,
+            ShadingModel::CookTorrance {
+                metallic: 1.0,
+                roughness: 0.3
+            }
+        );
+        assert_eq!(scene.material.ka, 0.0);
+        assert_eq!(scene.ambient, Color::new(1.0, 1.0, 1.0));
+        assert!(scene.lights.is_empty());
+    }
+
+    #[test]
+    fn reads_version_6() {
+        let dir = TempDir::new("v6");
+        write_png(&dir.join("t.png"));
+        let json = scene_json(
+            6,
+            r#""orientation": [1, 0, 0, 0],
+            "material": { "color": null, "texture": "t.png", "normal_map": "t.png",
+                "ka": 0.2, "kd": 0.4, "ks": 0.6, "m": 10,
+                "shading": { "model": "blinn-phong" } },
+            "ambient": [0.1, 0.2, 0.3],
+            "lights": [
+                { "kind": { "type": "point" }, "pos": [0, 0, 500], "dir": [0, 0, -1],
+                  "color": [1, 1, 1], "intensity": 1, "attenuation": [0.5, 0.01, 0.001],
+                  "t": 0, "is_animating": false }
+            ]"#,
+        );
+        let path = dir.join("scene.json");
+        std::fs::write(&path, json).unwrap();
+        let (scene, _) = Scene::open(&path).unwrap();
+
+        assert_eq!(scene.ambient, Color::new(0.1, 0.2, 0.3));
+        assert_eq!(scene.material.ka, 0.2);
+        assert_eq!(scene.material.shading, ShadingModel::BlinnPhong);
+        assert_eq!(
+            scene.lights[0].attenuation,
+            Attenuation {
+                constant: 0.5,
+                linear: 0.01,
+                quadratic: 0.001
+            }
+        );
+        // the filters were added in version 7
+        let Coloring::Texture(t) = &scene.material.coloring else {
+            panic!("expected a texture");
+        };
+        assert_eq!(t.filter, TextureFilter::Bilinear);
+        assert_eq!(t.path.as_deref(), Some(dir.join("t.png").as_path()));
+        let normal_map = scene.material.normal_map.as_ref().unwrap();
+        assert_eq!(normal_map.filter, TextureFilter::Bilinear);
+    }
+
+    #[test]
+    fn reads_version_7() {
+        let dir = TempDir::new("v7");
+        write_png(&dir.join("t.png"));
+        let json = scene_json(
+            7,
+            r#""orientation": [1, 0, 0, 0],
+            "material": { "color": null, "texture": "t.png", "normal_map": "t.png",
+                "texture_filter": "anisotropic", "normal_map_filter": "nearest",
+                "ka": 0.2, "kd": 0.4, "ks": 0.6, "m": 10,
+                "shading": { "model": "phong" } }"#,
+        );
+        let path = dir.join("scene.json");
+        std::fs::write(&path, json).unwrap();
+        let (scene, _) = Scene::open(&path).unwrap();
+
+        let Coloring::Texture(t) = &scene.material.coloring else {
+            panic!("expected a texture");
+        };
+        assert_eq!(t.filter, TextureFilter::Anisotropic);
+        let normal_map = scene.material.normal_map.as_ref().unwrap();
+        assert_eq!(normal_map.filter, TextureFilter::Nearest);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let json = scene_json(
+            VERSION + 1,
+            &format!(r#""material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }}"#),
+        );
+        let e = open_json("newer", &json).err().unwrap();
+        assert!(e.contains("is newer than the supported"), "{e}");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let with = |name: &str, fields: &str| open_json(name, &scene_json(VERSION, fields));
+        let light = |attenuation: &str| {
+            format!(
+                r#""material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }},
+                "lights": [
+                    {{ "kind": {{ "type": "point" }}, "pos": [0, 0, 500], "dir": [0, 0, -1],
+                       "color": [1, 1, 1], "intensity": 1, "attenuation": {attenuation},
+                       "t": 0, "is_animating": false }}
+                ]"#
+            )
+        };
+        assert!(with("attenuation", &light("[0.5, 0, 0.001]")).is_ok());
+        let e = with("zero-attenuation", &light("[0, 0, 0]")).err().unwrap();
+        assert!(e.contains("should not all be zero"), "{e}");
+        assert!(with("negative-attenuation", &light("[1, -1, 0]")).is_err());
+
+        let color = r#""material": { "color": [2, 0, 0], "texture": null, "normal_map": null,
+            "kd": 0.4, "ks": 0.6, "m": 10 }"#;
+        assert!(with("color", color).is_err());
+        let orientation = format!(
+            r#""orientation": [0, 0, 0, 0],
+            "material": {{ {SOLID}, "kd": 0.4, "ks": 0.6, "m": 10 }}"#
+        );
+        assert!(with("orientation", &orientation).is_err());
+
+        let material = |fields: &str| format!(r#""material": {{ {SOLID}, {fields} }}"#);
+        assert!(with("valid", &material(r#""kd": 0.4, "ks": 0.6, "m": 10"#)).is_ok());
+        let e = with("kd", &material(r#""kd": 1.5, "ks": 0.6, "m": 10"#))
+            .err()
+            .unwrap();
+        assert!(e.contains("kd should be in 0..=1"), "{e}");
+        assert!(with("ks", &material(r#""kd": 0.4, "ks": -0.1, "m": 10"#)).is_err());
+        assert!(with("ka", &material(r#""ka": 2, "kd": 0.4, "ks": 0.6, "m": 10"#)).is_err());
+        let e = with("m", &material(r#""kd": 0.4, "ks": 0.6, "m": 0"#))
+            .err()
+            .unwrap();
+        assert!(e.contains("m should be positive"), "{e}");
+    }
+}
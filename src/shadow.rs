@@ -0,0 +1,516 @@
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_3},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{
+    camera::Camera,
+    light::{Light, LightKind},
+    point::{Point3, Vector3},
+    render::ShadowOptions,
+    triangle::Triangle,
+};
+
+/// Height in texels of the bands of a face rendered in parallel
+const BAND_ROWS: usize = 32;
+
+/// Widest outer angle of a spotlight rendered into a single perspective view,
+/// wider cones are rendered into a cube as for point lights
+const MAX_SPOT_ANGLE: f32 = FRAC_PI_3;
+
+/// Shadow map of a light: the depth of the closest surface as seen from it,
+/// in the six faces of a cube around point lights, in a single perspective view
+/// along the axis of spotlights, or in a single parallel projection for
+/// directional lights
+pub struct ShadowMap {
+    resolution: usize,
+    bias: f32,
+    /// cube faces looking along +x, -x, +y, -y, +z and -z, or the single view
+    faces: Vec<Face>,
+}
+
+struct Face {
+    view: View,
+    /// depth keys of the closest surface texel by texel, see [`View::project`]
+    keys: Vec<f32>,
+}
+
+/// Projection of the world onto a face of the shadow map
+enum View {
+    Perspective(Camera),
+    /// looking along `forward` at the rectangle spanned by `right` and `up` from
+    /// `origin`, with texels `texel` world units wide
+    Parallel {
+        origin: Point3,
+        right: Vector3,
+        up: Vector3,
+        forward: Vector3,
+        texel: f32,
+    },
+}
+
+impl View {
+    /// Texel coordinates of `p` on a `res` x `res` face, with a depth key growing
+    /// towards the light and linear in texel coordinates (1 / depth in perspective,
+    /// minus depth in parallel projections), `None` behind the near plane
+    fn project(&self, p: Point3, res: f32) -> Option<Point3> {
+        match self {
+            View::Perspective(camera) => camera.project(p, res, res),
+            View::Parallel {
+                origin,
+                right,
+                up,
+                forward,
+                texel,
+            } => {
+                let d = p - *origin;
+                Some(Point3::new(
+                    d.dot(*right) / texel,
+                    d.dot(*up) / texel,
+                    -d.dot(*forward),
+                ))
+            }
+        }
+    }
+
+    /// Depth of a key returned by [`View::project`]
+    fn depth(&self, key: f32) -> f32 {
+        match self {
+            View::Perspective(_) if key <= 0.0 => f32::INFINITY,
+            View::Perspective(_) => 1.0 / key,
+            View::Parallel { .. } => -key,
+        }
+    }
+
+    /// World size of a texel at `p` on a `res` x `res` face
+    fn texel_size(&self, p: Point3, res: f32) -> f32 {
+        match self {
+            View::Perspective(camera) => camera.pixel_size(camera.depth(p), res),
+            View::Parallel { texel, .. } => *texel,
+        }
+    }
+}
+
+impl View {
+    /// Texel coordinates and depth keys of the vertices of `t`, see [`View::project`],
+    /// `None` if it crosses the near plane
+    fn project_triangle(&self, t: &Triangle, res: f32) -> Option<[Point3; 3]> {
+        Some([
+            self.project(t.p0.pos(), res)?,
+            self.project(t.p1.pos(), res)?,
+            self.project(t.p2.pos(), res)?,
+        ])
+    }
+}
+
+/// Shadow maps of the lights of a scene kept between frames, each rendered again
+/// only once its light moves or turns, the surface changes or the shadow options do
+#[derive(Default)]
+pub struct ShadowMaps {
+    maps: Vec<ShadowMap>,
+    /// kind, position and direction of the lights the maps were rendered for
+    lights: Vec<(LightKind, Point3, Vector3)>,
+    /// options the maps were rendered with, `None` once the surface changed
+    options: Option<ShadowOptions>,
+}
+
+impl ShadowMaps {
+    /// Marks all of the maps out of date, after the surface has changed
+    pub fn invalidate(&mut self) {
+        self.options = None;
+    }
+
+    /// Maps of `lights` in order, the ones out of date rendered again from
+    /// `triangles` (in world space) by `threads` threads
+    pub fn update(
+        &mut self,
+        lights: &[Light],
+        triangles: &[Triangle],
+        options: &ShadowOptions,
+        threads: usize,
+    ) -> &[ShadowMap] {
+        let all = self.options != Some(*options);
+        self.maps.truncate(lights.len());
+        self.lights.truncate(lights.len());
+        for (i, light) in lights.iter().enumerate() {
+            let key = (light.kind, light.pos, light.dir);
+            if !all && self.lights.get(i) == Some(&key) {
+                continue;
+            }
+            if i == self.maps.len() {
+                self.maps.push(ShadowMap::empty());
+                self.lights.push(key);
+            }
+            self.lights[i] = key;
+            self.maps[i].render(light, triangles, options, threads);
+        }
+        self.options = Some(*options);
+        &self.maps
+    }
+}
+
+impl ShadowMap {
+    fn empty() -> Self {
+        Self {
+            resolution: 0,
+            bias: 0.0,
+            faces: Vec::new(),
+        }
+    }
+
+    /// Renders the depth of `triangles` (in world space) as seen from `light`
+    /// into the buffers of the map, the faces split into bands of rows filled
+    /// by `threads` threads
+    fn render(
+        &mut self,
+        light: &Light,
+        triangles: &[Triangle],
+        options: &ShadowOptions,
+        threads: usize,
+    ) {
+        let resolution = options.resolution.max(1);
+        let views = match light.kind {
+            LightKind::Directional => vec![Self::parallel_view(light.dir, triangles, resolution)],
+            LightKind::Spot { outer, .. } if outer <= MAX_SPOT_ANGLE => {
+                vec![Self::spot_view(light, outer, resolution)]
+            }
+            LightKind::Point | LightKind::Spot { .. } => Self::cube_views(light.pos),
+        };
+        self.resolution = resolution;
+        self.bias = options.bias;
+        self.faces.truncate(views.len());
+        for (i, view) in views.into_iter().enumerate() {
+            match self.faces.get_mut(i) {
+                Some(face) => face.view = view,
+                None => self.faces.push(Face {
+                    view,
+                    keys: Vec::new(),
+                }),
+            }
+        }
+
+        let res = resolution as f32;
+        let projected: Vec<Vec<[Point3; 3]>> = self
+            .faces
+            .iter()
+            .map(|face| {
+                triangles
+                    .iter()
+                    .filter_map(|t| face.view.project_triangle(t, res))
+                    .collect()
+            })
+            .collect();
+        let mut bands = Vec::new();
+        for (face, triangles) in self.faces.iter_mut().zip(&projected) {
+            face.keys.resize(resolution * resolution, f32::NEG_INFINITY);
+            let rows = face.keys.chunks_mut(BAND_ROWS * resolution);
+            bands.extend(
+                rows.enumerate()
+                    .map(|(i, keys)| (i * BAND_ROWS, keys, triangles)),
+            );
+        }
+
+        let queue = Mutex::new(bands.into_iter());
+        thread::scope(|s| {
+            for _ in 0..threads.max(1) {
+                s.spawn(|| {
+                    loop {
+                        let Some((y0, keys, triangles)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        keys.fill(f32::NEG_INFINITY);
+                        for &t in triangles {
+                            fill_depth(keys, y0, resolution, t);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Perspective view along the axis of a spotlight, just covering its cone
+    fn spot_view(light: &Light, outer: f32, resolution: usize) -> View {
+        let dir = light.dir.normalized();
+        let up = if dir.y.abs() < 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        // two texels of margin on each side for the filtering
+        let res = resolution as f32;
+        let half = (outer.max(1e-2).tan() * res / (res - 4.0).max(1.0)).atan();
+        View::Perspective(Camera {
+            position: light.pos,
+            target: light.pos + dir,
+            up,
+            fov: 2.0 * half,
+            near: 1.0,
+            far: 1e5,
+        })
+    }
+
+    fn cube_views(pos: Point3) -> Vec<View> {
+        let y = Vector3::new(0.0, 1.0, 0.0);
+        let z = Vector3::new(0.0, 0.0, 1.0);
+        let face = |dir: Vector3, up: Vector3| {
+            View::Perspective(Camera {
+                position: pos,
+                target: pos + dir,
+                up,
+                fov: FRAC_PI_2,
+                near: 1.0,
+                far: 1e5,
+            })
+        };
+        vec![
+            face(Vector3::new(1.0, 0.0, 0.0), y),
+            face(Vector3::new(-1.0, 0.0, 0.0), y),
+            face(y, z),
+            face(y * -1.0, z),
+            face(z, y),
+            face(z * -1.0, y),
+        ]
+    }
+
+    /// Parallel view along `dir` just covering all of the triangles
+    fn parallel_view(dir: Vector3, triangles: &[Triangle], resolution: usize) -> View {
+        let forward = dir.normalized();
+        let helper = if forward.y.abs() < 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let right = forward.cross(helper).normalized();
+        let up = right.cross(forward);
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        let points = triangles.iter().flat_map(|t| [t.p0, t.p1, t.p2]);
+        for p in points.map(|v| v.pos() - Point3::origin()) {
+            for (i, axis) in [right, up, forward].into_iter().enumerate() {
+                min[i] = min[i].min(p.dot(axis));
+                max[i] = max[i].max(p.dot(axis));
+            }
+        }
+        if triangles.is_empty() {
+            (min, max) = ([0.0; 3], [1.0; 3]);
+        }
+
+        // a texel of margin on each side
+        let extent = (max[0] - min[0]).max(max[1] - min[1]).max(1e-3);
+        let texel = extent / (resolution as f32 - 2.0).max(1.0);
+        let origin = Point3::origin()
+            + right * (min[0] - texel)
+            + up * (min[1] - texel)
+            + forward * (min[2] - 1.0);
+        View::Parallel {
+            origin,
+            right,
+            up,
+            forward,
+            texel,
+        }
+    }
+
+    /// Fraction of the light reaching `p` on a surface with normal `n`, filtered
+    /// over 3 x 3 texels (PCF), with `to_light` the unit vector towards the light
+    pub fn lit(&self, p: Point3, n: Vector3, to_light: Vector3) -> f32 {
+        let face = if self.faces.len() == 1 {
+            &self.faces[0]
+        } else {
+            &self.faces[Self::face_index(to_light * -1.0)]
+        };
+        let res = self.resolution as f32;
+
+        // moving the point off the surface by a texel keeps surfaces lit at grazing
+        // angles from shadowing themselves, where the bias alone is not enough
+        let texel = face.view.texel_size(p, res);
+        let n = if n.dot(to_light) < 0.0 { n * -1.0 } else { n };
+        let Some(s) = face.view.project(p + n * texel, res) else {
+            return 1.0;
+        };
+
+        // the depth of a surface lit at a grazing angle changes quickly from texel to
+        // texel, the filtered texels up to two texels away are compared allowing for it
+        let cos = n.dot(to_light).clamp(0.1, 1.0);
+        let slope = texel * (1.0 - cos * cos).sqrt() / cos;
+        let depth = face.view.depth(s.z) - self.bias - 2.0 * slope;
+        let max = self.resolution as i32 - 1;
+        let (x, y) = (s.x.round() as i32, s.y.round() as i32);
+        // outside the view of a spotlight, where it does not shine
+        if !((-1..=max + 1).contains(&x) && (-1..=max + 1).contains(&y)) {
+            return 1.0;
+        }
+        let mut lit = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let tx = (x + dx).clamp(0, max) as usize;
+                let ty = (y + dy).clamp(0, max) as usize;
+                let key = face.keys[ty * self.resolution + tx];
+                if depth <= face.view.depth(key) {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f32 / 9.0
+    }
+
+    /// Cube face looking along the dominant axis of `d`
+    fn face_index(d: Vector3) -> usize {
+        let (ax, ay, az) = (d.x.abs(), d.y.abs(), d.z.abs());
+        if ax >= ay && ax >= az {
+            if d.x > 0.0 { 0 } else { 1 }
+        } else if ay >= az {
+            if d.y > 0.0 { 2 } else { 3 }
+        } else if d.z > 0.0 {
+            4
+        } else {
+            5
+        }
+    }
+}
+
+/// Rasterises the depth of a triangle projected to `[a, b, c]` into the band of
+/// rows starting at `y0` of a face `resolution` texels wide, keeping the closest
+/// value (largest key) at each texel
+fn fill_depth(keys: &mut [f32], y0: usize, resolution: usize, [a, b, c]: [Point3; 3]) {
+    let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+    if area.abs() < 1e-6 {
+        return;
+    }
+
+    let (res, rows) = (resolution as f32, (keys.len() / resolution) as f32);
+    let x0 = a.x.min(b.x).min(c.x).ceil().max(0.0);
+    let x1 = a.x.max(b.x).max(c.x).floor().min(res - 1.0);
+    let y0f = y0 as f32;
+    let top = a.y.min(b.y).min(c.y).ceil().max(y0f);
+    let bottom = a.y.max(b.y).max(c.y).floor().min(y0f + rows - 1.0);
+    if x0 > x1 || top > bottom {
+        return;
+    }
+
+    for y in top as usize..=bottom as usize {
+        for x in x0 as usize..=x1 as usize {
+            let (px, py) = (x as f32, y as f32);
+            let l0 = ((c.x - b.x) * (py - b.y) - (c.y - b.y) * (px - b.x)) / area;
+            let l1 = ((a.x - c.x) * (py - c.y) - (a.y - c.y) * (px - c.x)) / area;
+            let l2 = 1.0 - l0 - l1;
+            if l0 < 0.0 || l1 < 0.0 || l2 < 0.0 {
+                continue;
+            }
+
+            let key = l0 * a.z + l1 * b.z + l2 * c.z;
+            let k = &mut keys[(y - y0) * resolution + x];
+            if key > *k {
+                *k = key;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, triangle::Vertex};
+
+    /// Square of two triangles facing up at height `z`, `size` wide around the OZ axis
+    fn square(z: f32, size: f32) -> [Triangle; 2] {
+        let (pu, pv) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let v = |x: f32, y: f32| {
+            let p = Point3::new(x * size / 2.0, y * size / 2.0, z);
+            Vertex::new(p, n, pu, pv, 0.0, 0.0)
+        };
+        [
+            Triangle::new(v(-1.0, -1.0), v(1.0, -1.0), v(1.0, 1.0)),
+            Triangle::new(v(-1.0, -1.0), v(1.0, 1.0), v(-1.0, 1.0)),
+        ]
+    }
+
+    /// Point light, spotlight and directional light shining down from `pos`
+    fn lights(pos: Point3) -> Vec<Light> {
+        let point = Light::new(pos, Color::new(1.0, 1.0, 1.0));
+        let down = Vector3::new(0.0, 0.0, -1.0);
+        let spot = Light {
+            kind: LightKind::spot(),
+            dir: down,
+            ..point.clone()
+        };
+        let sun = Light {
+            kind: LightKind::Directional,
+            dir: down,
+            ..point.clone()
+        };
+        vec![point, spot, sun]
+    }
+
+    fn options(bias: f32) -> ShadowOptions {
+        ShadowOptions {
+            enabled: true,
+            resolution: 256,
+            bias,
+        }
+    }
+
+    /// Fraction of the light of each of `lights` reaching the floor at `p`
+    fn lit(maps: &[ShadowMap], lights: &[Light], p: Point3) -> Vec<f32> {
+        let n = Vector3::new(0.0, 0.0, 1.0);
+        let lit = |(map, light): (&ShadowMap, &Light)| map.lit(p, n, light.incidence(p).0);
+        maps.iter().zip(lights).map(lit).collect()
+    }
+
+    #[test]
+    fn occluder_shadows_the_points_behind_it() {
+        let lights = lights(Point3::new(0.0, 0.0, 500.0));
+        let triangles: Vec<Triangle> = square(0.0, 800.0)
+            .into_iter()
+            .chain(square(100.0, 100.0))
+            .collect();
+        let mut maps = ShadowMaps::default();
+        let maps = maps.update(&lights, &triangles, &options(2.0), 2);
+        let faces: Vec<usize> = maps.iter().map(|m| m.faces.len()).collect();
+        assert_eq!(faces, [6, 1, 1]);
+
+        assert_eq!(lit(maps, &lights, Point3::origin()), [0.0; 3]);
+        assert_eq!(lit(maps, &lights, Point3::new(150.0, 0.0, 0.0)), [1.0; 3]);
+        assert_eq!(lit(maps, &lights, Point3::new(-120.0, 60.0, 0.0)), [1.0; 3]);
+    }
+
+    #[test]
+    fn maps_follow_the_surface_once_invalidated() {
+        let lights = lights(Point3::new(0.0, 0.0, 500.0));
+        let mut triangles: Vec<Triangle> = square(0.0, 800.0).into();
+        let mut maps = ShadowMaps::default();
+        maps.update(&lights, &triangles, &options(2.0), 1);
+
+        triangles.extend(square(100.0, 100.0));
+        let unchanged = maps.update(&lights, &triangles, &options(2.0), 1);
+        assert_eq!(lit(unchanged, &lights, Point3::origin()), [1.0; 3]);
+        maps.invalidate();
+        let updated = maps.update(&lights, &triangles, &options(2.0), 1);
+        assert_eq!(lit(updated, &lights, Point3::origin()), [0.0; 3]);
+    }
+
+    #[test]
+    fn bias_keeps_a_flat_patch_from_shadowing_itself() {
+        let triangles = square(0.0, 800.0);
+        let points: Vec<Point3> = (0..20)
+            .flat_map(|i| (0..20).map(move |j| (i, j)))
+            .map(|(i, j)| Point3::new(i as f32 * 15.0 - 150.0, j as f32 * 15.0 - 150.0, 0.0))
+            .collect();
+        // from overhead down to grazing angles, where the depth of the texels varies the most
+        for height in [700.0, 300.0, 150.0, 60.0] {
+            let mut lights = lights(Point3::new(-700.0, 100.0, height));
+            for light in &mut lights {
+                light.dir = (Point3::origin() - light.pos).normalized();
+            }
+            let mut maps = ShadowMaps::default();
+            let bias = ShadowOptions::default().bias;
+            let maps = maps.update(&lights, &triangles, &options(bias), 2);
+            for &p in &points {
+                assert_eq!(lit(maps, &lights, p), [1.0; 3], "{p:?} lit from {height}");
+            }
+        }
+    }
+}
@@ -1,80 +1,133 @@
-use std::str::FromStr;
+use std::{iter::Peekable, str::FromStr};
 
-use eframe::egui::{Color32, Painter, Stroke, pos2};
+use eframe::egui::{Color32, Painter, Pos2, Stroke};
 
 use crate::{
-    mesh::Mesh,
     point::{Point3, Vector3},
     triangle::{Triangle, Vertex},
 };
 
 pub struct BezierSurface {
-    points: [[Point3; 4]; 4],
+    /// control net of (m + 1) rows with (n + 1) points each,
+    /// `points[j][i]` lies at index `i` along u and `j` along v
+    points: Vec<Vec<Point3>>,
+    /// weights of the control points, all ones for a polynomial surface
+    weights: Vec<Vec<f32>>,
     /// advancement in animation
     t: f32,
     /// original position of animated point
     orig_pos: Point3,
-    rot_ox: f32,
-    rot_oz: f32,
 }
 
 impl BezierSurface {
-    pub fn rotate_ox(&mut self, delta: f32) {
-        self.rot_ox += delta;
-        self.points
-            .iter_mut()
-            .for_each(|r| r.iter_mut().for_each(|p| p.rotate_ox(delta)));
+    pub fn new(points: Vec<Vec<Point3>>, weights: Vec<Vec<f32>>) -> Result<Self, String> {
+        let cols = points.first().map_or(0, |r| r.len());
+        if points.len() < 2 || cols < 2 {
+            return Err("surface needs at least 2 control points in each direction".into());
+        }
+        if points.iter().any(|r| r.len() != cols) {
+            return Err("all rows of the control net must have the same length".into());
+        }
+        if weights.len() != points.len() || weights.iter().any(|r| r.len() != cols) {
+            return Err("expected exactly one weight per control point".into());
+        }
+        if weights.iter().flatten().any(|&w| w <= 0.0) {
+            return Err("weights should be positive".into());
+        }
+
+        Ok(Self {
+            orig_pos: points[1][1],
+            points,
+            weights,
+            t: 0.0,
+        })
+    }
+
+    /// Degree (n, m) of the surface in u and v respectively
+    pub fn degree(&self) -> (usize, usize) {
+        (self.points[0].len() - 1, self.points.len() - 1)
     }
 
-    pub fn rotate_oz(&mut self, delta: f32) {
-        self.rot_oz += delta;
+    /// Applies `f` to every control point
+    pub fn map_points(&mut self, f: impl Fn(Point3) -> Point3) {
         self.points
             .iter_mut()
-            .for_each(|r| r.iter_mut().for_each(|p| p.rotate_oz(delta)));
+            .for_each(|r| r.iter_mut().for_each(|p| *p = f(*p)));
+        self.orig_pos = f(self.orig_pos);
     }
 
-    pub fn evaluate(&self, u: f32, v: f32) -> Vertex {
-        fn bernstein(i: usize, t: f32) -> f32 {
-            match i {
-                0 => (1.0 - t).powi(3),
-                1 => 3.0 * t * (1.0 - t).powi(2),
-                2 => 3.0 * t * t * (1.0 - t),
-                3 => t.powi(3),
-                _ => unreachable!(),
-            }
+    pub fn points(&self) -> impl Iterator<Item = &Point3> {
+        self.points.iter().flatten()
+    }
+
+    /// Control net with the animated point at its original position
+    pub fn rest_points(&self) -> Vec<Vec<Point3>> {
+        let mut points = self.points.clone();
+        points[1][1] = self.orig_pos;
+        points
+    }
+
+    pub fn weights(&self) -> &[Vec<f32>] {
+        &self.weights
+    }
+
+    pub fn point(&self, row: usize, col: usize) -> Point3 {
+        self.points[row][col]
+    }
+
+    pub fn move_point(&mut self, row: usize, col: usize, delta: Vector3) {
+        self.points[row][col] += delta;
+        if (row, col) == (1, 1) {
+            self.orig_pos += delta;
         }
+    }
 
-        fn deriv(i: usize, t: f32) -> f32 {
-            match i {
-                0 => -3.0 * (1.0 - t).powi(2),
-                1 => 3.0 * (1.0 - t).powi(2) - 6.0 * t * (1.0 - t),
-                2 => 6.0 * t * (1.0 - t) - 3.0 * t.powi(2),
-                3 => 3.0 * t.powi(2),
-                _ => unreachable!(),
-            }
+    pub fn evaluate(&self, u: f32, v: f32) -> Vertex {
+        let (p, mut pu, mut pv) = self.derivatives(u, v);
+        let mut n = pu.cross(pv);
+        if n.length() <= 1e-4 * pu.length() * pv.length() {
+            // collapsed edge (e.g. the teapot lid), take the frame from slightly inside
+            (_, pu, pv) = self.derivatives(u + (0.5 - u) * 1e-3, v + (0.5 - v) * 1e-3);
+            n = pu.cross(pv);
         }
+        Vertex::new(p, n.normalized(), pu, pv, u, v)
+    }
 
-        let mut p = Point3::origin();
-        let mut pu = Vector3::zeros();
-        let mut pv = Vector3::zeros();
-        for j in 0..4 {
-            let bv = bernstein(j, v);
-            let dbv = deriv(j, v);
-            for i in 0..4 {
-                let bu = bernstein(i, u);
-                let dbu = deriv(i, u);
-                let pt = self.points[j][i];
-                let w = bu * bv;
-                p = p + pt * w;
-                pu = pu + pt * dbu * bv;
-                pv = pv + pt * bu * dbv;
+    /// Evaluates the rational surface S = A / W, where A is the weighted sum
+    /// of control points and W the sum of weights (both in the Bernstein basis),
+    /// returns S with its partial derivatives
+    fn derivatives(&self, u: f32, v: f32) -> (Point3, Vector3, Vector3) {
+        let (n, m) = self.degree();
+
+        let mut a = Point3::origin();
+        let mut au = Vector3::zeros();
+        let mut av = Vector3::zeros();
+        let (mut w, mut wu, mut wv) = (0.0, 0.0, 0.0);
+        for j in 0..=m {
+            let bv = bernstein(m, j, v);
+            let dbv = bernstein_deriv(m, j, v);
+            for i in 0..=n {
+                let bu = bernstein(n, i, u);
+                let dbu = bernstein_deriv(n, i, u);
+                let wt = self.weights[j][i];
+                let pt = self.points[j][i] * wt;
+                a = a + pt * (bu * bv);
+                au = au + pt * (dbu * bv);
+                av = av + pt * (bu * dbv);
+                w += wt * bu * bv;
+                wu += wt * dbu * bv;
+                wv += wt * bu * dbv;
             }
         }
-        let n = pu.cross(pv).normalized();
-        Vertex::new(p, n, pu, pv, u, v)
+
+        // quotient rule: S_u = (A_u - S * W_u) / W
+        let p = a * (1.0 / w);
+        let pu = (au - p * wu) * (1.0 / w);
+        let pv = (av - p * wv) * (1.0 / w);
+        (p, pu, pv)
     }
 
-    pub fn triangulate(&self, resolution: usize) -> Mesh {
+    pub(crate) fn triangulate(&self, resolution: usize) -> Vec<Triangle> {
         let mut triangles = Vec::new();
 
         let n = resolution - 1;
@@ -93,30 +146,30 @@ impl BezierSurface {
                 triangles.push(Triangle::new(p00, p11, p01));
             }
         }
-        Mesh::new(triangles, resolution)
-    }
-
-    pub fn draw_points(&self, painter: &Painter) {
-        let ctx = painter.ctx();
-        for y in 0..4 {
-            for x in 0..4 {
-                let p = self.points[y][x].to_viewport_center(ctx);
-                painter.circle_filled(pos2(p.x, p.y), 6.0, Color32::RED);
-
-                if x + 1 < 4 {
-                    let p_next = self.points[y][x + 1].to_viewport_center(ctx);
-                    painter.line_segment(
-                        [pos2(p.x, p.y), pos2(p_next.x, p_next.y)],
-                        Stroke::new(1.0, Color32::LIGHT_RED),
-                    );
+        triangles
+    }
+
+    pub fn draw_points(&self, painter: &Painter, project: impl Fn(Point3) -> Option<Pos2>) {
+        let rows = self.points.len();
+        let cols = self.points[0].len();
+        let stroke = Stroke::new(1.0, Color32::LIGHT_RED);
+        for y in 0..rows {
+            for x in 0..cols {
+                let Some(p) = project(self.points[y][x]) else {
+                    continue;
+                };
+                painter.circle_filled(p, 6.0, Color32::RED);
+
+                if x + 1 < cols
+                    && let Some(p_next) = project(self.points[y][x + 1])
+                {
+                    painter.line_segment([p, p_next], stroke);
                 }
 
-                if y + 1 < 4 {
-                    let p_next = self.points[y + 1][x].to_viewport_center(ctx);
-                    painter.line_segment(
-                        [pos2(p.x, p.y), pos2(p_next.x, p_next.y)],
-                        Stroke::new(1.0, Color32::LIGHT_RED),
-                    );
+                if y + 1 < rows
+                    && let Some(p_next) = project(self.points[y + 1][x])
+                {
+                    painter.line_segment([p, p_next], stroke);
                 }
             }
         }
@@ -128,33 +181,202 @@ impl BezierSurface {
             self.t = 0.0;
         }
 
-        let mut v = Vector3::new(0.0, 1.0, 0.0) * 7e2 * self.t.sin();
-        v.rotate_ox(self.rot_ox);
-        v.rotate_oz(self.rot_oz);
-
+        let v = Vector3::new(0.0, 1.0, 0.0) * 7e2 * self.t.sin();
         self.points[1][1] = self.orig_pos + v;
     }
 }
 
+/// Binomial coefficient `n` choose `k`
+fn binomial(n: usize, k: usize) -> f32 {
+    (0..k.min(n - k)).fold(1.0, |acc, i| acc * (n - i) as f32 / (i + 1) as f32)
+}
+
+/// i-th Bernstein basis polynomial of degree n
+fn bernstein(n: usize, i: usize, t: f32) -> f32 {
+    if i > n {
+        return 0.0;
+    }
+    binomial(n, i) * t.powi(i as i32) * (1.0 - t).powi((n - i) as i32)
+}
+
+/// Derivative of the i-th Bernstein basis polynomial of degree n
+fn bernstein_deriv(n: usize, i: usize, t: f32) -> f32 {
+    if n == 0 {
+        return 0.0;
+    }
+    let lower = if i == 0 {
+        0.0
+    } else {
+        bernstein(n - 1, i - 1, t)
+    };
+    n as f32 * (lower - bernstein(n - 1, i, t))
+}
+
+/// Parses `x y z` with an optional trailing weight `w` (1 if omitted)
+fn parse_control_point(line: &str) -> Result<(Point3, f32), String> {
+    let coords: Vec<_> = line.split_whitespace().collect();
+    if coords.len() != 4 {
+        return Ok((line.parse()?, 1.0));
+    }
+
+    let w: f32 = coords[3]
+        .parse()
+        .map_err(|e| format!("w should be a real number, {e}"))?;
+    Ok((coords[..3].join(" ").parse()?, w))
+}
+
+impl BezierSurface {
+    /// Reads a single patch from `lines`, see [`FromStr`] for the format
+    pub fn read_patch<'a>(
+        lines: &mut Peekable<impl Iterator<Item = &'a str>>,
+    ) -> Result<Self, String> {
+        let (n, m) = match lines
+            .peek()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        {
+            Some(header) if header.len() == 2 => {
+                let n: usize = header[0]
+                    .parse()
+                    .map_err(|e| format!("degree in u should be a natural number, {e}"))?;
+                let m: usize = header[1]
+                    .parse()
+                    .map_err(|e| format!("degree in v should be a natural number, {e}"))?;
+                lines.next();
+                (n, m)
+            }
+            _ => (3, 3),
+        };
+
+        let mut points = vec![vec![Point3::origin(); n + 1]; m + 1];
+        let mut weights = vec![vec![1.0; n + 1]; m + 1];
+        for (row, row_w) in points.iter_mut().zip(weights.iter_mut()) {
+            for (p, w) in row.iter_mut().zip(row_w.iter_mut()) {
+                (*p, *w) = parse_control_point(lines.next().ok_or("expected point3")?)?;
+            }
+        }
+
+        Self::new(points, weights)
+    }
+
+    /// Reads consecutive patches until the end of input
+    pub fn parse_patches(s: &str) -> Result<Vec<Self>, String> {
+        let mut lines = s.lines().filter(|l| !l.trim().is_empty()).peekable();
+
+        let mut patches = Vec::new();
+        while lines.peek().is_some() {
+            let patch = Self::read_patch(&mut lines)
+                .map_err(|e| format!("patch {}: {e}", patches.len() + 1))?;
+            patches.push(patch);
+        }
+
+        if patches.is_empty() {
+            return Err("expected at least one patch".into());
+        }
+        Ok(patches)
+    }
+}
+
 impl FromStr for BezierSurface {
     type Err = String;
 
+    /// Reads an optional `n m` header with the degrees in u and v
+    /// (bicubic if omitted) followed by (n + 1) * (m + 1) points, row by row.
+    /// Each point may carry a fourth `w` column with its weight.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut points = [[Point3::origin(); 4]; 4];
-        let mut lines = s.lines().filter(|l| l.len() > 2);
+        let mut lines = s.lines().filter(|l| !l.trim().is_empty()).peekable();
+        Self::read_patch(&mut lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_degree_header() {
+        let s = "2 1\n\
+                 0 0 0\n1 0 0\n2 0 0\n\
+                 0 1 0\n1 1 1\n2 1 0\n";
+        let patch: BezierSurface = s.parse().unwrap();
+        assert_eq!(patch.degree(), (2, 1));
+        assert_eq!(patch.points[1][1], Point3::new(1.0, 1.0, 1.0));
+    }
 
-        for row in points.iter_mut() {
-            for p in row.iter_mut() {
-                *p = lines.next().ok_or("expected point3")?.parse()?;
+    #[test]
+    fn defaults_to_bicubic() {
+        let patch: BezierSurface = include_str!("../assets/points.txt").parse().unwrap();
+        assert_eq!(patch.degree(), (3, 3));
+        assert_eq!(patch.points[3][3], Point3::new(180.0, -210.0, 200.0));
+    }
+
+    #[test]
+    fn rejects_missing_points() {
+        let s = "1 1\n0 0 0\n1 0 0\n0 1 0\n";
+        assert!(s.parse::<BezierSurface>().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_degree() {
+        assert!(
+            "x 1\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n"
+                .parse::<BezierSurface>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn bernstein_basis_interpolates_the_ends() {
+        for n in 1..=5 {
+            assert_eq!(bernstein(n, 0, 0.0), 1.0);
+            assert_eq!(bernstein(n, n, 1.0), 1.0);
+            for t in [0.0, 0.3, 0.8] {
+                let sum: f32 = (0..=n).map(|i| bernstein(n, i, t)).sum();
+                assert!((sum - 1.0).abs() < 1e-5, "{n}, {t}: {sum}");
             }
         }
+    }
 
-        Ok(Self {
-            points,
-            t: 0.0,
-            orig_pos: points[1][1],
-            rot_ox: 0.0,
-            rot_oz: 0.0,
-        })
+    #[test]
+    fn reads_weights() {
+        let patch: BezierSurface = include_str!("../assets/cylinder.txt").parse().unwrap();
+        assert_eq!(patch.degree(), (2, 1));
+        assert!((patch.weights[0][1] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert_eq!(patch.weights[1][0], 1.0);
+    }
+
+    #[test]
+    fn rational_patch_is_an_exact_cylinder() {
+        let patch: BezierSurface = include_str!("../assets/cylinder.txt").parse().unwrap();
+        for i in 0..=10 {
+            let p = patch.evaluate(i as f32 / 10.0, 0.3).pos();
+            assert!((p.x.hypot(p.z) - 200.0).abs() < 1e-2, "{p:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_nonpositive_weights() {
+        let s = "1 1\n0 0 0 1\n1 0 0 0\n0 1 0 1\n1 1 0 1\n";
+        assert!(s.parse::<BezierSurface>().is_err());
+    }
+
+    #[test]
+    fn parses_consecutive_patches() {
+        let s = format!(
+            "{}\n{}",
+            include_str!("../assets/points.txt"),
+            include_str!("../assets/cylinder.txt")
+        );
+        let patches = BezierSurface::parse_patches(&s).unwrap();
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].degree(), (3, 3));
+        assert_eq!(patches[1].degree(), (2, 1));
+    }
+
+    #[test]
+    fn reports_the_invalid_patch() {
+        let s = format!("{}\n1 1\n0 0 0\n", include_str!("../assets/points.txt"));
+        let e = BezierSurface::parse_patches(&s).err().unwrap();
+        assert!(e.starts_with("patch 2:"), "{e}");
+        assert!(BezierSurface::parse_patches("\n \n").is_err());
     }
 }
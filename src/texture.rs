@@ -1,42 +1,325 @@
+use std::path::{Path, PathBuf};
+
 use image::ImageBuffer;
+use serde::{Deserialize, Serialize};
+
+use crate::{color, point::Vector3};
 
-use crate::point::Vector3;
+/// Most samples taken along the longer axis of the pixel footprint
+/// by [`TextureFilter::Anisotropic`]
+const MAX_ANISOTROPY: usize = 8;
+
+/// How the texels around the sampled point are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    /// the texel the point falls into
+    Nearest,
+    /// weighted average of the 2 x 2 closest texels
+    #[default]
+    Bilinear,
+    /// bilinear samples of the two mipmap levels closest to the size of the pixel
+    /// footprint on the texture, blended together
+    Trilinear,
+    /// trilinear samples spread along the longer axis of a stretched footprint,
+    /// at the level of its shorter axis
+    Anisotropic,
+}
+
+/// Change of the texture coordinates from a pixel to its right (`dx`)
+/// and lower (`dy`) neighbour
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UvDerivatives {
+    pub dx: (f32, f32),
+    pub dy: (f32, f32),
+}
+
+/// Level of the mipmap pyramid, each halved in size from the previous one
+#[derive(Debug)]
+struct MipLevel {
+    width: u32,
+    height: u32,
+    /// RGBA texels in the space they are filtered in
+    texels: Vec<[f32; 4]>,
+}
 
 #[derive(Debug)]
 pub struct Texture {
     pub width: u32,
     pub height: u32,
+    /// RGBA pixels as read from the file
     pub data: Vec<u8>,
+    /// file the texture was loaded from
+    pub path: Option<PathBuf>,
+    pub filter: TextureFilter,
+    /// full size image followed by the levels halved down to 1 x 1
+    mipmaps: Vec<MipLevel>,
 }
 
 impl Texture {
-    pub fn from_img(img: ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Self {
+    /// Texture of the image pixels, decoded from sRGB into linear space if `srgb`
+    /// (colors) or taken as they are otherwise (normal maps), so that the texels
+    /// are averaged in the space they are used in
+    pub fn from_img(img: ImageBuffer<image::Rgba<u8>, Vec<u8>>, srgb: bool) -> Self {
         let (width, height) = img.dimensions();
         let data = img.into_raw();
+        let decode: [f32; 256] = std::array::from_fn(|i| {
+            let c = i as f32 / 255.0;
+            if srgb { color::srgb_to_linear(c) } else { c }
+        });
+        let texels = data
+            .chunks_exact(4)
+            .map(|p| {
+                [
+                    decode[p[0] as usize],
+                    decode[p[1] as usize],
+                    decode[p[2] as usize],
+                    p[3] as f32 / 255.0,
+                ]
+            })
+            .collect();
+        let mipmaps = build_mipmaps(MipLevel {
+            width,
+            height,
+            texels,
+        });
         Self {
             width,
             height,
             data,
+            path: None,
+            filter: TextureFilter::default(),
+            mipmaps,
+        }
+    }
+
+    /// Color texture read from an sRGB encoded image
+    pub fn open(path: &Path) -> Result<Self, String> {
+        Self::open_encoded(path, true)
+    }
+
+    /// Normal map read without decoding the image
+    pub fn open_normal_map(path: &Path) -> Result<Self, String> {
+        Self::open_encoded(path, false)
+    }
+
+    fn open_encoded(path: &Path, srgb: bool) -> Result<Self, String> {
+        let img = image::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            ..Self::from_img(img.to_rgba8(), srgb)
+        })
+    }
+
+    /// Number of mipmap levels, including the full size image
+    pub fn levels(&self) -> usize {
+        self.mipmaps.len()
+    }
+
+    /// Color at (u, v) with components in 0..=1, linear for a color texture and
+    /// as stored for a normal map, filtered with [`Texture::filter`] over
+    /// the footprint of a pixel given by `d`
+    pub fn sample(&self, u: f32, v: f32, d: &UvDerivatives) -> [f32; 4] {
+        let (u, v) = (u.clamp(0.0, 1.0), 1.0 - v.clamp(0.0, 1.0)); // flip y
+        let (w, h) = (self.width as f32, self.height as f32);
+        let dx = (d.dx.0 * w, -d.dx.1 * h);
+        let dy = (d.dy.0 * w, -d.dy.1 * h);
+        let (len_x, len_y) = (dx.0.hypot(dx.1), dy.0.hypot(dy.1));
+
+        match self.filter {
+            TextureFilter::Nearest => self.nearest(u, v),
+            TextureFilter::Bilinear => self.bilinear(0, u, v),
+            TextureFilter::Trilinear => self.trilinear(len_x.max(len_y).log2(), u, v),
+            TextureFilter::Anisotropic => {
+                let (major, minor, axis) = if len_x >= len_y {
+                    (len_x, len_y, dx)
+                } else {
+                    (len_y, len_x, dy)
+                };
+                let n = ((major / minor.max(1e-6)).ceil() as usize).clamp(1, MAX_ANISOTROPY);
+                let lod = (major / n as f32).log2();
+                if n == 1 {
+                    return self.trilinear(lod, u, v);
+                }
+                // samples evenly spaced along the major axis, in texture coordinates
+                let step = (axis.0 / w / n as f32, axis.1 / h / n as f32);
+                let mut sum = [0.0; 4];
+                for i in 0..n {
+                    let t = i as f32 - (n - 1) as f32 / 2.0;
+                    let s = self.trilinear(lod, u + step.0 * t, v + step.1 * t);
+                    for (a, b) in sum.iter_mut().zip(s) {
+                        *a += b / n as f32;
+                    }
+                }
+                sum
+            }
         }
     }
 
-    pub fn sample(&self, u: f32, v: f32) -> [u8; 4] {
-        let x = (u.clamp(0.0, 1.0) * (self.width - 1) as f32) as usize;
-        let y = ((1.0 - v.clamp(0.0, 1.0)) * (self.height - 1) as f32) as usize; // flip y
-        let idx = (y * self.width as usize + x) * 4;
-        self.data[idx..idx + 4].try_into().unwrap()
+    /// Texel the point (u, v) falls into, with v growing downwards
+    fn nearest(&self, u: f32, v: f32) -> [f32; 4] {
+        let x = (u * (self.width - 1) as f32) as usize;
+        let y = (v * (self.height - 1) as f32) as usize;
+        self.mipmaps[0].texels[y * self.width as usize + x]
+    }
+
+    /// Bilinear sample of mipmap `level` at (u, v), with v growing downwards
+    fn bilinear(&self, level: usize, u: f32, v: f32) -> [f32; 4] {
+        let MipLevel {
+            width: w,
+            height: h,
+            texels,
+        } = &self.mipmaps[level];
+        let (w, h) = (*w, *h);
+        let x = u.clamp(0.0, 1.0) * (w - 1) as f32;
+        let y = v.clamp(0.0, 1.0) * (h - 1) as f32;
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(w as usize - 1), (y0 + 1).min(h as usize - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let texel = |x: usize, y: usize, c: usize| texels[y * w as usize + x][c];
+        std::array::from_fn(|c| {
+            let top = texel(x0, y0, c) * (1.0 - fx) + texel(x1, y0, c) * fx;
+            let bottom = texel(x0, y1, c) * (1.0 - fx) + texel(x1, y1, c) * fx;
+            top * (1.0 - fy) + bottom * fy
+        })
+    }
+
+    /// Blend of the bilinear samples of the two levels around `lod`
+    /// (log2 of the footprint size in texels)
+    fn trilinear(&self, lod: f32, u: f32, v: f32) -> [f32; 4] {
+        // without derivatives (NaN) the full size image is used
+        let lod = lod.max(0.0).min((self.levels() - 1) as f32);
+        let level = lod.floor() as usize;
+        let t = lod - level as f32;
+        let a = self.bilinear(level, u, v);
+        if t == 0.0 {
+            return a;
+        }
+        let b = self.bilinear(level + 1, u, v);
+        std::array::from_fn(|c| a[c] * (1.0 - t) + b[c] * t)
     }
 
     /// Interprets the texture as a normal map with
     /// Nx \in [-1, 1], Ny \in [-1, 1], Nz \in [0, 1]
     /// from r, g, b respectively
-    pub fn sample_normal(&self, u: f32, v: f32) -> Vector3 {
-        let col = self.sample(u, v);
-        let map = |c| (c / 255.0 - 0.5) * 2.0;
+    pub fn sample_normal(&self, u: f32, v: f32, d: &UvDerivatives) -> Vector3 {
+        let col = self.sample(u, v, d);
+        let map = |c| (c - 0.5) * 2.0;
         Vector3 {
-            x: map(col[0] as f32),
-            y: map(col[1] as f32),
-            z: map(col[2] as f32).max(0.0),
+            x: map(col[0]),
+            y: map(col[1]),
+            z: map(col[2]).max(0.0),
+        }
+    }
+}
+
+/// Halves the image repeatedly down to 1 x 1, averaging 2 x 2 texels
+fn build_mipmaps(image: MipLevel) -> Vec<MipLevel> {
+    let mut levels = vec![image];
+    loop {
+        let prev = levels.last().unwrap();
+        let (w, h) = (prev.width as usize, prev.height as usize);
+        if w == 1 && h == 1 {
+            return levels;
         }
+        let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+        let mut texels = Vec::with_capacity(nw * nh);
+        for y in 0..nh {
+            for x in 0..nw {
+                // the last row or column of an odd size is dropped,
+                // a side of a single texel is repeated
+                let xs = [(2 * x).min(w - 1), (2 * x + 1).min(w - 1)];
+                let ys = [(2 * y).min(h - 1), (2 * y + 1).min(h - 1)];
+                let mut sum = [0.0; 4];
+                for sy in ys {
+                    for sx in xs {
+                        for (s, t) in sum.iter_mut().zip(prev.texels[sy * w + sx]) {
+                            *s += t / 4.0;
+                        }
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+        levels.push(MipLevel {
+            width: nw as u32,
+            height: nh as u32,
+            texels,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black and white checker of single texels
+    fn checker(size: u32, srgb: bool) -> Texture {
+        let img = ImageBuffer::from_fn(size, size, |x, y| {
+            let c = if (x + y) % 2 == 0 { 0 } else { 255 };
+            image::Rgba([c, c, c, 255])
+        });
+        Texture::from_img(img, srgb)
+    }
+
+    #[test]
+    fn builds_mipmaps_down_to_one_texel() {
+        let texture = checker(8, true);
+        assert_eq!(texture.levels(), 4);
+        let last = texture.mipmaps.last().unwrap();
+        assert_eq!((last.width, last.height), (1, 1));
+
+        let img = ImageBuffer::from_pixel(5, 2, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(Texture::from_img(img, true).levels(), 3);
+    }
+
+    #[test]
+    fn averages_colors_in_linear_space() {
+        let texture = checker(4, true);
+        for level in &texture.mipmaps[1..] {
+            for texel in &level.texels {
+                assert!((texel[0] - 0.5).abs() < 1e-6, "{texel:?}");
+                assert_eq!(texel[3], 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_normal_maps_as_stored() {
+        let img = ImageBuffer::from_pixel(2, 2, image::Rgba([128, 128, 255, 255]));
+        let mut texture = Texture::from_img(img, false);
+        texture.filter = TextureFilter::Trilinear;
+        let d = UvDerivatives {
+            dx: (1.0, 0.0),
+            dy: (0.0, 1.0),
+        };
+        let n = texture.sample_normal(0.5, 0.5, &d);
+        assert!(n.x.abs() < 0.01 && n.y.abs() < 0.01 && (n.z - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn distant_checker_filters_to_mid_grey() {
+        let mut texture = checker(64, true);
+        // a pixel covering the whole texture
+        let d = UvDerivatives {
+            dx: (1.0, 0.0),
+            dy: (0.0, 1.0),
+        };
+        for filter in [TextureFilter::Trilinear, TextureFilter::Anisotropic] {
+            texture.filter = filter;
+            let c = texture.sample(0.3, 0.6, &d);
+            assert!((c[0] - 0.5).abs() < 1e-3, "{filter:?}: {c:?}");
+        }
+        texture.filter = TextureFilter::Nearest;
+        assert!([0.0, 1.0].contains(&texture.sample(0.3, 0.6, &d)[0]));
+    }
+
+    #[test]
+    fn bilinear_blends_neighbouring_texels() {
+        let img = ImageBuffer::from_fn(2, 1, |x, _| image::Rgba([(x * 255) as u8, 0, 0, 255]));
+        let texture = Texture::from_img(img, false);
+        let c = texture.sample(0.25, 0.0, &UvDerivatives::default());
+        assert!((c[0] - 0.25).abs() < 1e-6);
     }
 }
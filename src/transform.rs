@@ -0,0 +1,266 @@
+use std::ops::Mul;
+
+use crate::point::{Point3, Vector3};
+
+/// Unit quaternion describing a rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    /// Rotation by `angle` radians around `axis`
+    pub fn from_axis_angle(axis: Vector3, angle: f32) -> Self {
+        let a = axis.normalized();
+        let (s, c) = (angle * 0.5).sin_cos();
+        Self {
+            w: c,
+            x: a.x * s,
+            y: a.y * s,
+            z: a.z * s,
+        }
+    }
+
+    /// Rotation around OX by `ox`, then OY by `oy`, then OZ by `oz` (all in radians)
+    pub fn from_euler(ox: f32, oy: f32, oz: f32) -> Self {
+        Self::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), oz)
+            * Self::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), oy)
+            * Self::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), ox)
+    }
+
+    /// Angles (ox, oy, oz) such that [`Quaternion::from_euler`] gives back this rotation,
+    /// with oy in -90°..=90°
+    pub fn to_euler(self) -> (f32, f32, f32) {
+        let Self { w, x, y, z } = self;
+        let ox = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+        let oy = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+        let oz = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+        (ox, oy, oz)
+    }
+
+    pub fn normalized(self) -> Self {
+        let l = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Self {
+            w: self.w / l,
+            x: self.x / l,
+            y: self.y / l,
+            z: self.z / l,
+        }
+    }
+
+    /// Inverse rotation
+    pub fn conjugate(self) -> Self {
+        Self {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(self, v: Vector3) -> Vector3 {
+        // v' = v + 2w (q x v) + 2 q x (q x v), with q the vector part
+        let q = Vector3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        v + t * self.w + q.cross(t)
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+
+    /// Rotation by `rhs` followed by `self`
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
+/// Affine transform of homogeneous coordinates, `m[row][col]`, applied to column vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn rotation(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q.normalized();
+        Self {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// View transform of a camera at `eye` looking at `target`, in view space
+    /// the camera looks down -z with x to the right and y up
+    pub fn look_at(eye: Point3, target: Point3, up: Vector3) -> Self {
+        let f = (target - eye).normalized();
+        let r = f.cross(up).normalized();
+        let u = r.cross(f);
+        let e = eye - Point3::origin();
+        Self {
+            m: [
+                [r.x, r.y, r.z, -r.dot(e)],
+                [u.x, u.y, u.z, -u.dot(e)],
+                [-f.x, -f.y, -f.z, f.dot(e)],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Applies the linear part only, for directions and (under rotations
+    /// and uniform scaling) normals
+    pub fn transform_vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+
+    /// Transform by `rhs` followed by `self`
+    fn mul(self, rhs: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, c) in row.iter_mut().enumerate() {
+                *c = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::triangle::Vertex;
+
+    fn assert_close(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn euler_angles_round_trip() {
+        for (ox, oy, oz) in [(0.3, -0.2, 1.1), (-2.5, 1.2, -0.4), (1.8, 0.0, 3.0)] {
+            let (x, y, z) = Quaternion::from_euler(ox, oy, oz).to_euler();
+            assert!((x - ox).abs() < 1e-4 && (y - oy).abs() < 1e-4 && (z - oz).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn quaternion_and_matrix_rotate_alike() {
+        let q = Quaternion::from_axis_angle(Vector3::new(1.0, -2.0, 0.5), 0.9);
+        let m = Matrix4::rotation(q);
+        let v = Vector3::new(3.0, 1.0, -2.0);
+        let p = m.transform_point(Point3::origin() + v);
+        assert_close(q.rotate(v), p - Point3::origin());
+    }
+
+    #[test]
+    fn products_apply_the_right_factor_first() {
+        let a =
+            Quaternion::from_axis_angle(Vector3::new(0.0, 0.0, 1.0), 0.5 * std::f32::consts::PI);
+        let b =
+            Quaternion::from_axis_angle(Vector3::new(1.0, 0.0, 0.0), 0.5 * std::f32::consts::PI);
+        let v = Vector3::new(0.0, 1.0, 0.0);
+        // b takes y to z, which a leaves in place
+        assert_close((a * b).rotate(v), a.rotate(b.rotate(v)));
+        assert_close((a * b).rotate(v), Vector3::new(0.0, 0.0, 1.0));
+
+        let p = Point3::new(0.0, 1.0, 0.0);
+        let m = Matrix4::rotation(a) * Matrix4::rotation(b);
+        assert_close(
+            m.transform_point(p) - Point3::origin(),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn look_at_puts_the_target_ahead() {
+        let eye = Point3::new(100.0, -200.0, 300.0);
+        let target = Point3::new(-50.0, 20.0, 10.0);
+        let view = Matrix4::look_at(eye, target, Vector3::new(0.0, 0.0, 1.0));
+        let distance = (target - eye).length();
+        assert_close(
+            view.transform_point(eye) - Point3::origin(),
+            Vector3::zeros(),
+        );
+        assert_close(
+            view.transform_point(target) - Point3::origin(),
+            Vector3::new(0.0, 0.0, -distance),
+        );
+    }
+
+    #[test]
+    fn vertices_carry_their_frame_along() {
+        let q = Quaternion::from_euler(0.4, -1.0, 2.0);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let (pu, pv) = (Vector3::new(2.0, 0.0, 0.0), Vector3::new(0.0, 3.0, 0.0));
+        let v = Vertex::new(Point3::new(1.0, 2.0, 3.0), normal, pu, pv, 0.0, 0.0);
+        let moved = v.transformed(&Matrix4::rotation(q));
+        assert_close(
+            moved.pos() - Point3::origin(),
+            q.rotate(Vector3::new(1.0, 2.0, 3.0)),
+        );
+        assert_close(moved.normal(), q.rotate(normal));
+        assert_close(moved.pu(), q.rotate(pu));
+        assert_close(moved.pv(), q.rotate(pv));
+    }
+}
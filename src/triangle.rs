@@ -1,12 +1,20 @@
-use eframe::egui::{Color32, Painter, Pos2, Stroke, pos2};
+use eframe::egui::{Color32, Painter, Pos2, Stroke};
 
 use crate::{
-    canvas::Canvas,
-    light::Light,
+    camera::Camera,
+    canvas::{Canvas, Tile},
+    light::Lighting,
     material::Material,
     point::{Point3, Vector3},
+    render::{Rasterizer, RenderOptions},
+    shadow::ShadowMap,
+    texture::UvDerivatives,
+    transform::Matrix4,
 };
 
+/// Normal vectors are drawn every this many pixels in both directions
+const NORMALS_DENSITY: usize = 10;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vertex {
     pos: Point3,
@@ -29,28 +37,113 @@ impl Vertex {
         }
     }
 
-    pub fn rotate_ox(&mut self, rot: f32) {
-        self.pos.rotate_ox(rot);
-        self.pu.rotate_ox(rot);
-        self.pv.rotate_ox(rot);
-        self.normal.rotate_ox(rot);
+    pub fn pos(&self) -> Point3 {
+        self.pos
+    }
+
+    pub fn normal(&self) -> Vector3 {
+        self.normal
+    }
+
+    pub fn pu(&self) -> Vector3 {
+        self.pu
+    }
+
+    pub fn pv(&self) -> Vector3 {
+        self.pv
     }
 
-    pub fn rotate_oz(&mut self, rot: f32) {
-        self.pos.rotate_oz(rot);
-        self.pu.rotate_oz(rot);
-        self.pv.rotate_oz(rot);
-        self.normal.rotate_oz(rot);
+    pub fn uv(&self) -> (f32, f32) {
+        (self.u, self.v)
+    }
+
+    /// Vertex a fraction `t` of the way to `other`, with all of its attributes
+    /// interpolated linearly
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            pos: self.pos + (other.pos - self.pos) * t,
+            normal: self.normal + (other.normal - self.normal) * t,
+            pu: self.pu + (other.pu - self.pu) * t,
+            pv: self.pv + (other.pv - self.pv) * t,
+            u: self.u + (other.u - self.u) * t,
+            v: self.v + (other.v - self.v) * t,
+        }
+    }
+
+    /// Vertex moved by `m`, with its normal and tangents following the rotation
+    pub fn transformed(&self, m: &Matrix4) -> Self {
+        Self {
+            pos: m.transform_point(self.pos),
+            normal: m.transform_vector(self.normal).normalized(),
+            pu: m.transform_vector(self.pu),
+            pv: m.transform_vector(self.pv),
+            ..*self
+        }
+    }
+}
+
+/// Triangle projected to the screen together with the shading inputs
+struct Fill<'a> {
+    /// vertices in pixels, with z = 1 / depth
+    screen: [Point3; 3],
+    /// change of the screen space barycentric coordinates from a pixel
+    /// to its right and lower neighbour
+    baryc_dx: (f32, f32, f32),
+    baryc_dy: (f32, f32, f32),
+    camera: &'a Camera,
+    lighting: &'a Lighting<'a>,
+    /// shadow maps of the lights, empty without shadows
+    shadows: &'a [ShadowMap],
+    material: &'a Material,
+    options: &'a RenderOptions,
+}
+
+impl<'a> Fill<'a> {
+    fn new(
+        screen: [Point3; 3],
+        camera: &'a Camera,
+        lighting: &'a Lighting<'a>,
+        shadows: &'a [ShadowMap],
+        material: &'a Material,
+        options: &'a RenderOptions,
+    ) -> Self {
+        let [s0, s1, s2] = screen;
+        let area = (s1.x - s0.x) * (s2.y - s0.y) - (s1.y - s0.y) * (s2.x - s0.x);
+        Self {
+            screen,
+            baryc_dx: (
+                (s1.y - s2.y) / area,
+                (s2.y - s0.y) / area,
+                (s0.y - s1.y) / area,
+            ),
+            baryc_dy: (
+                (s2.x - s1.x) / area,
+                (s0.x - s2.x) / area,
+                (s1.x - s0.x) / area,
+            ),
+            camera,
+            lighting,
+            shadows,
+            material,
+            options,
+        }
+    }
+
+    /// 1 / depth at screen space barycentric coordinates, it is linear in screen space
+    fn depth(&self, baryc: &Baryc) -> f32 {
+        let [s0, s1, s2] = self.screen;
+        baryc.interp(s0.z, s1.z, s2.z)
     }
 }
 
 struct Baryc((f32, f32, f32));
 
 impl Baryc {
-    pub fn new(tri: &Triangle, x: f32, y: f32, det: f32) -> Self {
-        let (x0, y0) = (tri.p0.pos.x, tri.p0.pos.y);
-        let (x1, y1) = (tri.p1.pos.x, tri.p1.pos.y);
-        let (x2, y2) = (tri.p2.pos.x, tri.p2.pos.y);
+    /// Coordinates of the pixel (x, y) in the triangle projected to `verts`
+    pub fn new(verts: &[Pos2; 3], x: f32, y: f32, det: f32) -> Self {
+        let (x0, y0) = (verts[0].x, verts[0].y);
+        let (x1, y1) = (verts[1].x, verts[1].y);
+        let (x2, y2) = (verts[2].x, verts[2].y);
 
         let l0 = ((y1 - y2) * (x - x2) + (x2 - x1) * (y - y2)) / det;
         let l1 = ((y2 - y0) * (x - x2) + (x0 - x2) * (y - y2)) / det;
@@ -59,6 +152,21 @@ impl Baryc {
         Self((l0, l1, l2))
     }
 
+    /// Coordinates moved by `d`
+    fn offset(&self, d: (f32, f32, f32)) -> Self {
+        let (l0, l1, l2) = self.0;
+        Self((l0 + d.0, l1 + d.1, l2 + d.2))
+    }
+
+    /// Corrects screen space coordinates for perspective, given 1 / w of the vertices,
+    /// as attributes divided by w are the ones varying linearly on the screen
+    pub fn perspective(self, inv_w: [f32; 3]) -> Self {
+        let (l0, l1, l2) = self.0;
+        let (l0, l1, l2) = (l0 * inv_w[0], l1 * inv_w[1], l2 * inv_w[2]);
+        let sum = l0 + l1 + l2;
+        Self((l0 / sum, l1 / sum, l2 / sum))
+    }
+
     pub fn interp<T>(&self, p0: T, p1: T, p2: T) -> T
     where
         T: std::ops::Mul<f32, Output = T> + std::ops::Add<T, Output = T>,
@@ -79,37 +187,119 @@ impl Triangle {
         Self { p0, p1, p2 }
     }
 
-    pub fn draw_outline(&self, painter: &Painter) {
+    pub fn transformed(&self, m: &Matrix4) -> Self {
+        Self::new(
+            self.p0.transformed(m),
+            self.p1.transformed(m),
+            self.p2.transformed(m),
+        )
+    }
+
+    /// Pushes onto `out` the part of the triangle in front of the near plane of
+    /// `camera`: the triangle itself, nothing, or the 1 or 2 triangles left after
+    /// cutting off the vertices behind the plane, keeping the winding order
+    pub fn clip_near(&self, camera: &Camera, out: &mut Vec<Triangle>) {
+        // cut slightly in front of the plane, so that the new vertices still project
+        let near = camera.near * (1.0 + 1e-4);
+        let verts = [self.p0, self.p1, self.p2];
+        let d = verts.map(|v| camera.depth(v.pos) - near);
+        let front = d.iter().filter(|&&d| d >= 0.0).count();
+        match front {
+            3 => out.push(*self),
+            0 => {}
+            _ => {
+                // start from the vertex alone on its side of the plane
+                let i = (0..3).find(|&i| (d[i] >= 0.0) == (front == 1)).unwrap();
+                let [a, b, c] = [i, (i + 1) % 3, (i + 2) % 3];
+                let ab = verts[a].lerp(&verts[b], d[a] / (d[a] - d[b]));
+                let ac = verts[a].lerp(&verts[c], d[a] / (d[a] - d[c]));
+                if front == 1 {
+                    out.push(Triangle::new(verts[a], ab, ac));
+                } else {
+                    out.push(Triangle::new(ab, verts[b], verts[c]));
+                    out.push(Triangle::new(ab, verts[c], ac));
+                }
+            }
+        }
+    }
+
+    pub fn draw_outline(&self, painter: &Painter, project: impl Fn(Point3) -> Option<Pos2>) {
         let stroke = Stroke::new(1.0, Color32::LIGHT_GREEN);
 
-        let p0 = self.p0.pos.to_viewport_center(painter.ctx()).projection();
-        let p1 = self.p1.pos.to_viewport_center(painter.ctx()).projection();
-        let p2 = self.p2.pos.to_viewport_center(painter.ctx()).projection();
+        let (Some(p0), Some(p1), Some(p2)) = (
+            project(self.p0.pos),
+            project(self.p1.pos),
+            project(self.p2.pos),
+        ) else {
+            return;
+        };
         painter.line(vec![p0, p1], stroke);
         painter.line(vec![p1, p2], stroke);
         painter.line(vec![p2, p0], stroke);
     }
 
-    fn determinant(&self) -> f32 {
-        let (x0, y0) = (self.p0.pos.x, self.p0.pos.y);
-        let (x1, y1) = (self.p1.pos.x, self.p1.pos.y);
-        let (x2, y2) = (self.p2.pos.x, self.p2.pos.y);
+    fn determinant(verts: &[Pos2; 3]) -> f32 {
+        let (x0, y0) = (verts[0].x, verts[0].y);
+        let (x1, y1) = (verts[1].x, verts[1].y);
+        let (x2, y2) = (verts[2].x, verts[2].y);
 
         (y1 - y2) * (x0 - x2) + (x2 - x1) * (y0 - y2)
     }
 
+    /// Range of rows (top, bottom) covered by the triangle on a `width` x `height`
+    /// image, `None` if it is not drawn at all
+    pub fn screen_rows(&self, camera: &Camera, width: f32, height: f32) -> Option<(f32, f32)> {
+        let ys = [
+            camera.project(self.p0.pos, width, height)?.y,
+            camera.project(self.p1.pos, width, height)?.y,
+            camera.project(self.p2.pos, width, height)?.y,
+        ];
+        let top = ys.iter().copied().fold(f32::INFINITY, f32::min);
+        let bottom = ys.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        Some((top, bottom))
+    }
+
+    /// Fills the part of the triangle lying in the rows of `tile`
     pub fn draw_filling(
         &self,
-        canvas: &mut Canvas,
-        light: &Light,
+        tile: &mut Tile,
+        camera: &Camera,
+        lighting: &Lighting,
+        shadows: &[ShadowMap],
         material: &Material,
-        draw_normals: bool,
+        options: &RenderOptions,
     ) {
-        let v0 = self.p0.pos.to_screen(canvas).projection();
-        let v1 = self.p1.pos.to_screen(canvas).projection();
-        let v2 = self.p2.pos.to_screen(canvas).projection();
+        let (w, h) = (tile.width() as f32, tile.height() as f32);
+        // triangles crossing the far plane are skipped, those crossing the near
+        // plane are expected to be cut by `clip_near` beforehand
+        let (Some(s0), Some(s1), Some(s2)) = (
+            camera.project(self.p0.pos, w, h),
+            camera.project(self.p1.pos, w, h),
+            camera.project(self.p2.pos, w, h),
+        ) else {
+            return;
+        };
+
+        let fill = Fill::new([s0, s1, s2], camera, lighting, shadows, material, options);
+        match options.rasterizer {
+            // coverage of several samples per pixel is only tested with edge functions
+            Rasterizer::Scanline if tile.sample_offsets().len() == 1 => {
+                self.fill_scanline(tile, &fill)
+            }
+            _ => self.fill_edge_function(tile, &fill),
+        }
+    }
 
-        let verts = [v0, v1, v2];
+    /// Scanline fill walking an edge table of the triangle sides. Spans run over the
+    /// pixel centres from the left edge up to, but without, the right one and rows
+    /// from the top vertex up to, but without, the bottom one, so that triangles
+    /// sharing an edge do not draw its pixels twice.
+    fn fill_scanline(&self, tile: &mut Tile, fill: &Fill) {
+        let verts = fill.screen.map(|s| s.projection());
+        let det = Self::determinant(&verts);
+        if det.abs() < 1e-6 {
+            return;
+        }
 
         let min_yf = verts
             .iter()
@@ -162,15 +352,17 @@ impl Triangle {
                 continue;
             }
 
+            // the edge enters the table at the first row it crosses
             edge_table[bucket_idx as usize].push(Edge {
                 y_max: p1.y,
-                x: p0.x,
+                x: p0.x + (p0.y.ceil() - p0.y) * inv_slope,
                 inv_slope,
             });
         }
 
-        let scan_y_start = y_min.max(0);
-        let scan_y_end = ((canvas.height() as i32) - 1).min(y_max);
+        let rows = tile.rows();
+        let scan_y_start = y_min.max(rows.start as i32);
+        let scan_y_end = (rows.end as i32 - 1).min(y_max);
 
         if scan_y_start > scan_y_end {
             return;
@@ -196,36 +388,21 @@ impl Triangle {
                 let xa = active_edges[i].x;
                 let xb = active_edges[i + 1].x;
 
+                // pixels on the right end belong to the neighbouring span, as
+                // with the top-left rule of the edge functions
                 let x_start = xa.ceil() as i32;
-                let x_end = xb.floor() as i32;
+                let x_end = xb.ceil() as i32 - 1;
 
                 if scan_y >= scan_y_start && scan_y <= scan_y_end {
                     let y = scan_y as usize;
-                    let x0 = x_start.max(0) as usize;
-                    let x1 = (canvas.width() as i32 - 1).min(x_end) as usize;
+                    let x0 = x_start.max(0);
+                    let x1 = (tile.width() as i32 - 1).min(x_end);
                     if x0 <= x1 {
-                        for x in x0..=x1 {
-                            let xf = x as f32;
-                            let yf = scan_y as f32;
-                            let Pos2 { x: xf, y: yf } = canvas.pos_from_screen(pos2(xf, yf));
-
-                            let baryc = Baryc::new(self, xf, yf, self.determinant());
-
-                            let p = baryc.interp(self.p0.pos, self.p1.pos, self.p2.pos);
-                            let u = baryc.interp(self.p0.u, self.p1.u, self.p2.u);
-                            let v = baryc.interp(self.p0.v, self.p1.v, self.p2.v);
-
-                            let n = baryc.interp(self.p0.normal, self.p1.normal, self.p2.normal).normalized();
-                            let pu = baryc.interp(self.p0.pu, self.p1.pu, self.p2.pu).normalized();
-                            let pv = baryc.interp(self.p0.pv, self.p1.pv, self.p2.pv).normalized();
-                            let n = material.normal_at(u, v, pu, pv, n).normalized();
-
-                            if draw_normals {
-                                self.draw_normals(canvas, x, y, n, p);
-                            }
-
-                            let color = self.color_for(u, v, n, p, light, material);
-                            canvas.put_pixel(x, y, p.z, color);
+                        for x in x0 as usize..=x1 as usize {
+                            let baryc = Baryc::new(&verts, x as f32, scan_y as f32, det);
+                            let z = fill.depth(&baryc);
+                            let color = self.shade(tile, fill, x, y, baryc);
+                            tile.put_pixel(x, y, z, color);
                         }
                     }
                 }
@@ -239,51 +416,439 @@ impl Triangle {
         }
     }
 
+    /// Half-space fill testing the edge functions of every pixel of the bounding box.
+    /// Vertices are snapped to 1/16 of a pixel so that the edge functions are exact
+    /// integers, updated incrementally along rows and columns, and samples lying exactly
+    /// on an edge are drawn only for top and left edges. Triangles sharing an edge
+    /// thus cover every sample along it exactly once.
+    ///
+    /// With multisampling each pixel is shaded once, at its first covered sample,
+    /// and the color is stored in the covered samples passing their own depth test.
+    fn fill_edge_function(&self, tile: &mut Tile, fill: &Fill) {
+        const SUBPIXEL: f32 = 16.0;
+        const STEP: i64 = SUBPIXEL as i64;
+
+        let v = fill.screen.map(|s| {
+            let x = (s.x * SUBPIXEL).round() as i64;
+            let y = (s.y * SUBPIXEL).round() as i64;
+            (x, y)
+        });
+        // twice the signed area of the triangle a, b, p
+        let edge = |(ax, ay): (i64, i64), (bx, by): (i64, i64), (px, py): (i64, i64)| {
+            (bx - ax) * (py - ay) - (by - ay) * (px - ax)
+        };
+        let area = edge(v[0], v[1], v[2]);
+        if area == 0 {
+            return;
+        }
+        let sign = area.signum();
+
+        let offsets = tile.sample_offsets();
+        let margin = offsets
+            .iter()
+            .map(|&(ox, oy)| ox.abs().max(oy.abs()) as i64)
+            .max()
+            .unwrap_or(0);
+
+        // pixels with samples inside the bounding box, clipped to the tile
+        let rows = tile.rows();
+        let (xs, ys) = (v.map(|p| p.0), v.map(|p| p.1));
+        let first = |c: [i64; 3]| (c.iter().min().unwrap() - margin + STEP - 1).div_euclid(STEP);
+        let last = |c: [i64; 3]| (c.iter().max().unwrap() + margin).div_euclid(STEP);
+        let x0 = first(xs).max(0);
+        let x1 = last(xs).min(tile.width() as i64 - 1);
+        let y0 = first(ys).max(rows.start as i64);
+        let y1 = last(ys).min(rows.end as i64 - 1);
+        if x0 > x1 || y0 > y1 {
+            return;
+        }
+
+        // edge i lies opposite to vertex i, its function is the i-th barycentric
+        // coordinate scaled by the area, oriented to be positive inside
+        let edges = [(v[1], v[2]), (v[2], v[0]), (v[0], v[1])];
+        let mut row_start = [0; 3];
+        let mut step_x = [0; 3];
+        let mut step_y = [0; 3];
+        let mut bias = [0; 3];
+        // change of the edge functions from the pixel centre to each sample
+        let mut sample_steps = vec![[0; 3]; offsets.len()];
+        for (i, &(a, b)) in edges.iter().enumerate() {
+            let (dx, dy) = ((b.0 - a.0) * sign, (b.1 - a.1) * sign);
+            row_start[i] = edge(a, b, (x0 * STEP, y0 * STEP)) * sign;
+            step_x[i] = -dy * STEP;
+            step_y[i] = dx * STEP;
+            for (steps, &(ox, oy)) in sample_steps.iter_mut().zip(offsets) {
+                steps[i] = -dy * ox as i64 + dx * oy as i64;
+            }
+            // with y pointing down the inside lies below a top edge
+            // and right of a left edge
+            let top_left = (dy == 0 && dx > 0) || dy < 0;
+            bias[i] = if top_left { 0 } else { -1 };
+        }
+
+        let inv_area = 1.0 / (area * sign) as f32;
+        let baryc = |e: [i64; 3]| {
+            Baryc((
+                e[0] as f32 * inv_area,
+                e[1] as f32 * inv_area,
+                e[2] as f32 * inv_area,
+            ))
+        };
+        let mut covered = Vec::with_capacity(offsets.len());
+        for y in y0..=y1 {
+            let mut e = row_start;
+            for x in x0..=x1 {
+                covered.clear();
+                for (s, steps) in sample_steps.iter().enumerate() {
+                    let es = [e[0] + steps[0], e[1] + steps[1], e[2] + steps[2]];
+                    if (0..3).all(|i| es[i] + bias[i] >= 0) {
+                        covered.push((s, es));
+                    }
+                }
+                if let Some(&(_, es)) = covered.first() {
+                    let (x, y) = (x as usize, y as usize);
+                    let color = self.shade(tile, fill, x, y, baryc(es));
+                    for &(s, es) in &covered {
+                        tile.put_sample(x, y, s, fill.depth(&baryc(es)), color);
+                    }
+                }
+                for i in 0..3 {
+                    e[i] += step_x[i];
+                }
+            }
+            for i in 0..3 {
+                row_start[i] += step_y[i];
+            }
+        }
+    }
+
+    /// Linear color of the pixel (x, y) at screen space barycentric coordinates `baryc`
+    fn shade(&self, tile: &mut Tile, fill: &Fill, x: usize, y: usize, baryc: Baryc) -> [f32; 3] {
+        let [s0, s1, s2] = fill.screen;
+        let options = fill.options;
+        let material = fill.material;
+
+        let correct = |b: Baryc| {
+            if options.perspective_correct {
+                b.perspective([s0.z, s1.z, s2.z])
+            } else {
+                b
+            }
+        };
+        let uv = |b: &Baryc| {
+            (
+                b.interp(self.p0.u, self.p1.u, self.p2.u),
+                b.interp(self.p0.v, self.p1.v, self.p2.v),
+            )
+        };
+        // texture coordinates at the neighbouring pixels give the footprint
+        // of the pixel on the textures
+        let right = uv(&correct(baryc.offset(fill.baryc_dx)));
+        let below = uv(&correct(baryc.offset(fill.baryc_dy)));
+
+        let baryc = correct(baryc);
+        let p = baryc.interp(self.p0.pos, self.p1.pos, self.p2.pos);
+        let (u, v) = uv(&baryc);
+        let d = UvDerivatives {
+            dx: (right.0 - u, right.1 - v),
+            dy: (below.0 - u, below.1 - v),
+        };
+
+        let n = baryc
+            .interp(self.p0.normal, self.p1.normal, self.p2.normal)
+            .normalized();
+        let pu = baryc
+            .interp(self.p0.pu, self.p1.pu, self.p2.pu)
+            .normalized();
+        let pv = baryc
+            .interp(self.p0.pv, self.p1.pv, self.p2.pv)
+            .normalized();
+        let n = material.normal_at(u, v, &d, pu, pv, n).normalized();
+
+        if options.draw_normals
+            && x.is_multiple_of(NORMALS_DENSITY * tile.scale())
+            && y.is_multiple_of(NORMALS_DENSITY * tile.scale())
+        {
+            tile.normals.push((p, n));
+        }
+
+        self.color_for((u, v), &d, n, p, fill)
+    }
+
+    /// Linear HDR color of the surface point `p` with normal `n`, summing the contributions
+    /// of the ambient light and all of the lights
     fn color_for(
         &self,
-        u: f32,
-        v: f32,
+        (u, v): (f32, f32),
+        d: &UvDerivatives,
         n: Vector3,
         p: Point3,
-        light: &Light,
-        material: &Material,
-    ) -> [u8; 4] {
-        let light_dir = (light.pos - p).normalized();
-        let il = n.dot(light_dir).max(0.0);
-
-        let r = n * (2.0 * n.dot(light_dir)) - light_dir;
-        let iz = Vector3::new(0.0, 0.0, 1.0).dot(r).powi(material.m);
-
-        // reflector
-        let p2l = (light_dir - p).normalized();
-        let o2l = (Vector3::zeros() - light_dir).normalized();
-        let fac = p2l.dot(o2l).max(0.0).powi(light.r);
-
-        let intensity = fac * (material.kd * il + material.ks * iz) * 255.0;
-
-        let col = material.color_at(u, v);
-        [
-            (light.color.r() * col.r() * intensity).min(255.0) as u8,
-            (light.color.g() * col.g() * intensity).min(255.0) as u8,
-            (light.color.b() * col.b() * intensity).min(255.0) as u8,
-            255,
-        ]
-    }
-
-    fn draw_normals(&self, canvas: &mut Canvas, x: usize, y: usize, n: Vector3, p: Point3) {
-        let density = 10;
-        if x.is_multiple_of(density) && y.is_multiple_of(density) {
-            let len = 10;
-            for i in 0..=len {
-                let p = p + n * (i as f32);
-                let sc = p.to_screen(canvas);
-                let col = if i == len {
-                    [0, 0, 255, 255]
-                } else {
-                    [255, 0, 0, 255]
-                };
-                canvas.put_pixel(sc.x as usize, sc.y as usize, sc.z, col);
+        fill: &Fill,
+    ) -> [f32; 3] {
+        let material = fill.material;
+        let to_eye = (fill.camera.position - p).normalized();
+
+        let col = material.color_at(u, v, d);
+        let mut sum = fill.lighting.ambient.as_slice();
+        for (s, c) in sum.iter_mut().zip(col.as_slice()) {
+            *s *= material.ka * c;
+        }
+        for (i, light) in fill.lighting.lights.iter().enumerate() {
+            let (light_dir, cone) = light.incidence(p);
+            let lit = fill.shadows.get(i).map_or(1.0, |s| s.lit(p, n, light_dir));
+            let reflected = material.reflectance(col, n, light_dir, to_eye);
+
+            let intensity = lit * cone * light.intensity;
+            for ((s, c), r) in sum.iter_mut().zip(light.color.as_slice()).zip(reflected) {
+                *s += c * intensity * r;
+            }
+        }
+        sum
+    }
+}
+
+/// Draws the normal vector `n` at `p` as a short line ending with a blue pixel
+pub fn draw_normal(canvas: &mut Canvas, camera: &Camera, p: Point3, n: Vector3) {
+    let len = 10;
+    let (w, h) = canvas.raster_size();
+    let (w, h) = (w as f32, h as f32);
+    for i in 0..=len {
+        let p = p + n * (i as f32);
+        let Some(sc) = camera.project(p, w, h) else {
+            continue;
+        };
+        // bright enough to stay saturated through tone mapping
+        let col = if i == len {
+            [0.0, 0.0, 1e3]
+        } else {
+            [1e3, 0.0, 0.0]
+        };
+        // a whole image pixel, so that supersampling does not thin out the line
+        let scale = canvas.scale();
+        let (x, y) = (sc.x as usize / scale * scale, sc.y as usize / scale * scale);
+        for dy in 0..scale {
+            for dx in 0..scale {
+                canvas.put_pixel(x + dx, y + dy, sc.z, col);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, render::ToneMapping};
+
+    const SIZE: usize = 40;
+
+    /// Triangle with its vertices already in pixels
+    fn flat(points: [(f32, f32); 3]) -> Triangle {
+        let [p0, p1, p2] = points.map(|(x, y)| {
+            let n = Vector3::new(0.0, 0.0, 1.0);
+            let (pu, pv) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+            Vertex::new(Point3::new(x, y, 0.0), n, pu, pv, 0.0, 0.0)
+        });
+        Triangle::new(p0, p1, p2)
+    }
+
+    /// Number of the triangles drawing into each pixel of a `SIZE` x `SIZE` image
+    fn coverage(rasterizer: Rasterizer, triangles: &[[(f32, f32); 3]]) -> Vec<u32> {
+        let camera = Camera::default();
+        let lighting = Lighting {
+            ambient: Color::new(1.0, 1.0, 1.0),
+            lights: &[],
+        };
+        let material = Material::default();
+        let options = RenderOptions::default();
+
+        let mut counts = vec![0; SIZE * SIZE];
+        for &points in triangles {
+            let mut canvas = Canvas::new(SIZE, SIZE);
+            let triangle = flat(points);
+            let screen = points.map(|(x, y)| Point3::new(x, y, 1e-3));
+            let fill = Fill::new(screen, &camera, &lighting, &[], &material, &options);
+            let tile = &mut canvas.tiles(SIZE)[0];
+            match rasterizer {
+                Rasterizer::Scanline => triangle.fill_scanline(tile, &fill),
+                Rasterizer::EdgeFunction => triangle.fill_edge_function(tile, &fill),
+            }
+            canvas.resolve(ToneMapping::Exposure, 0.0);
+            for (count, px) in counts.iter_mut().zip(canvas.buffer().chunks_exact(4)) {
+                *count += u32::from(px[3] > 0);
+            }
+        }
+        counts
+    }
+
+    /// Whether the pixel centre (x, y) lies inside the convex `polygon`, away from its sides
+    fn inside(polygon: &[(f32, f32)], x: usize, y: usize) -> bool {
+        let (x, y) = (x as f32, y as f32);
+        let sides = polygon.iter().zip(polygon.iter().cycle().skip(1));
+        let crosses: Vec<f32> = sides
+            .map(|(&(ax, ay), &(bx, by))| {
+                let len = (bx - ax).hypot(by - ay);
+                ((bx - ax) * (y - ay) - (by - ay) * (x - ax)) / len
+            })
+            .collect();
+        crosses.iter().all(|&c| c > 1e-3) || crosses.iter().all(|&c| c < -1e-3)
+    }
+
+    /// Asserts that the `triangles` tiling `polygon` draw every pixel inside it exactly once
+    fn assert_watertight(polygon: &[(f32, f32)], triangles: &[[(f32, f32); 3]]) {
+        let counts = coverage(Rasterizer::EdgeFunction, triangles);
+        for (i, &count) in counts.iter().enumerate() {
+            let (x, y) = (i % SIZE, i / SIZE);
+            assert!(count <= 1, "pixel ({x}, {y}) drawn {count} times");
+            if inside(polygon, x, y) {
+                assert_eq!(count, 1, "pixel ({x}, {y}) not drawn");
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edge_is_drawn_once() {
+        let quad = [(3.25, 2.5), (30.5, 5.0625), (27.0, 33.75), (4.125, 28.5)];
+        let [a, b, c, d] = quad;
+        assert_watertight(&quad, &[[a, b, c], [a, c, d]]);
+        // an edge through pixel centres
+        let quad = [(2.0, 2.0), (30.0, 2.0), (30.0, 30.0), (2.0, 30.0)];
+        let [a, b, c, d] = quad;
+        assert_watertight(&quad, &[[a, b, c], [c, d, a]]);
+    }
+
+    #[test]
+    fn fan_around_a_vertex_is_drawn_once() {
+        // the shared vertex lies on a pixel centre
+        let centre = (20.0, 20.0);
+        let ring: Vec<(f32, f32)> = (0..7)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::TAU / 7.0;
+                let snap = |c: f32| (c * 16.0).round() / 16.0;
+                (
+                    snap(20.0 + 15.0 * angle.cos()),
+                    snap(20.0 + 15.0 * angle.sin()),
+                )
+            })
+            .collect();
+        let fan: Vec<[(f32, f32); 3]> = (0..ring.len())
+            .map(|i| [centre, ring[i], ring[(i + 1) % ring.len()]])
+            .collect();
+        assert_watertight(&ring, &fan);
+        assert_eq!(coverage(Rasterizer::EdgeFunction, &fan)[20 * SIZE + 20], 1);
+    }
+
+    #[test]
+    fn scanline_covers_the_same_pixels() {
+        let triangles = [
+            [(3.25, 2.5), (30.5, 5.0625), (27.0, 33.75)],
+            [(3.25, 2.5), (27.0, 33.75), (4.125, 28.5)],
+            // flat top and bottom, vertices on pixel centres
+            [(2.0, 2.0), (30.0, 2.0), (16.0, 30.0)],
+            [(5.0, 8.0), (35.0, 37.0), (5.0, 37.0)],
+            [(20.5, 1.0), (38.9375, 20.25), (1.5, 38.5)],
+        ];
+        for t in triangles {
+            assert_eq!(
+                coverage(Rasterizer::Scanline, &[t]),
+                coverage(Rasterizer::EdgeFunction, &[t]),
+                "{t:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn clips_at_the_near_plane() {
+        let camera = Camera::default();
+        // depths along the default camera are 1500 - z
+        let vertex = |z: f32, u: f32| {
+            let n = Vector3::new(0.0, 0.0, 1.0);
+            let pos = Point3::new(u * 100.0, z * 0.1, z);
+            Vertex::new(pos, n, Vector3::zeros(), Vector3::zeros(), u, 0.0)
+        };
+        let near = 1500.0 - camera.near;
+        let clip = |t: Triangle| {
+            let mut out = Vec::new();
+            t.clip_near(&camera, &mut out);
+            out
+        };
+        let front = Triangle::new(vertex(0.0, 0.0), vertex(0.0, 1.0), vertex(100.0, 0.5));
+        assert_eq!(clip(front), vec![front]);
+        let behind = Triangle::new(
+            vertex(1600.0, 0.0),
+            vertex(1600.0, 1.0),
+            vertex(1495.0, 0.5),
+        );
+        assert!(clip(behind).is_empty());
+
+        let one_behind = Triangle::new(vertex(0.0, 0.0), vertex(1600.0, 1.0), vertex(0.0, 1.0));
+        let two_behind = Triangle::new(vertex(1600.0, 0.0), vertex(0.0, 1.0), vertex(1600.0, 1.0));
+        for (t, count) in [(one_behind, 2), (two_behind, 1)] {
+            let clipped = clip(t);
+            assert_eq!(clipped.len(), count);
+            let normal = |t: &Triangle| (t.p1.pos - t.p0.pos).cross(t.p2.pos - t.p0.pos);
+            for c in &clipped {
+                for v in [c.p0, c.p1, c.p2] {
+                    assert!(camera.project(v.pos, 100.0, 100.0).is_some());
+                    // attributes follow the position along the cut edges
+                    assert!((v.u - v.pos.x / 100.0).abs() < 1e-4);
+                    assert!((v.pos.y - v.pos.z * 0.1).abs() < 1e-3);
+                    assert!(v.pos.z <= near + 1e-3);
+                }
+                assert!(normal(c).normalized().dot(normal(&t).normalized()) > 0.999);
+            }
+        }
+    }
+
+    #[test]
+    fn perspective_correct_uv_follows_the_surface() {
+        // textured quad receding from the camera towards the top of the image
+        let vertex = |pos: Point3, u: f32, v: f32| {
+            let n = Vector3::new(0.0, 0.0, 1.0);
+            Vertex::new(pos, n, Vector3::zeros(), Vector3::zeros(), u, v)
+        };
+        let t = Triangle::new(
+            vertex(Point3::new(-200.0, -200.0, 300.0), 0.0, 0.0),
+            vertex(Point3::new(200.0, -200.0, 300.0), 1.0, 0.0),
+            vertex(Point3::new(-200.0, 200.0, -600.0), 0.0, 1.0),
+        );
+        let camera = Camera::default();
+        let (w, h) = (400.0, 400.0);
+        let screen = [t.p0, t.p1, t.p2].map(|v| camera.project(v.pos(), w, h).unwrap());
+        let verts = screen.map(|s| s.projection());
+        let det = Triangle::determinant(&verts);
+        // a pixel around the middle of the triangle on the screen
+        let x = (verts.iter().map(|p| p.x).sum::<f32>() / 3.0).round();
+        let y = (verts.iter().map(|p| p.y).sum::<f32>() / 3.0).round();
+        let baryc = || Baryc::new(&verts, x, y, det);
+        let uv = |b: Baryc| (b.interp(0.0, 1.0, 0.0), b.interp(0.0, 0.0, 1.0));
+        let inv_w = screen.map(|s| s.z);
+        let Baryc((l0, l1, l2)) = baryc();
+        assert!(
+            l0 > 0.0 && l1 > 0.0 && l2 > 0.0,
+            "pixel outside the triangle"
+        );
+
+        // where the ray through the pixel hits the plane of the quad
+        let (right, up, forward) = camera.basis();
+        let pixel = camera.pixel_size(1.0, h);
+        let dir = forward + right * ((x - w * 0.5) * pixel) - up * ((y - h * 0.5) * pixel);
+        let (e1, e2) = (t.p1.pos() - t.p0.pos(), t.p2.pos() - t.p0.pos());
+        let n = e1.cross(e2);
+        let hit = camera.position + dir * ((t.p0.pos() - camera.position).dot(n) / dir.dot(n));
+        let r = hit - t.p0.pos();
+        let (a, b, c) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+        let (d, e) = (e1.dot(r), e2.dot(r));
+        let expected = (
+            (c * d - b * e) / (a * c - b * b),
+            (a * e - b * d) / (a * c - b * b),
+        );
+
+        let (u, v) = uv(baryc().perspective(inv_w));
+        assert!(
+            (u - expected.0).abs() < 1e-3 && (v - expected.1).abs() < 1e-3,
+            "{u}, {v}"
+        );
+        let (u, v) = uv(baryc());
+        assert!((v - expected.1).abs() > 1e-2, "affine {u}, {v}");
+    }
+}
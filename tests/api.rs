@@ -0,0 +1,20 @@
+use bezier_surface::{BezierSurface, RenderOptions, Scene, render};
+
+#[test]
+fn renders_through_the_public_api() {
+    let patches = BezierSurface::parse_patches(include_str!("../assets/points.txt")).unwrap();
+    let mut scene = Scene::new(patches);
+    scene.set_rotation(0.3, 0.0, 0.2);
+
+    let (width, height) = (64, 48);
+    let mut buffer = vec![0; width * height * 4];
+    render(
+        &scene,
+        &RenderOptions::default(),
+        &mut buffer,
+        width,
+        height,
+    );
+    assert!(buffer.chunks_exact(4).any(|px| px[3] == 255));
+    assert!(buffer.chunks_exact(4).any(|px| px[3] == 0));
+}